@@ -0,0 +1,139 @@
+use rand::Rng;
+use rand::seq::IndexedRandom;
+
+use crate::TrainingConfig;
+
+// Punctuation stripped by the punctuation augmentation, besides ASCII punctuation
+const EXTRA_PUNCTUATION: &str = "¡¿«»‹›“”‘’„…–—。、，．！？；：「」『』（）【】《》〈〉・";
+// Punctuation the augmentation inserts
+const INSERTED_PUNCTUATION: &[char] = &['.', ',', '!', '?', ';', ':', '"', '\'', '(', ')', '-', '…'];
+
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || EXTRA_PUNCTUATION.contains(c)
+}
+
+// Whether any augmentation can fire; when none can, upsampling copies sentences verbatim
+// and draws nothing from the random stream
+pub fn augmentation_enabled(config: &TrainingConfig) -> bool {
+    [config.augment_crop, config.augment_case, config.augment_punctuation, config.augment_digits, config.augment_noise]
+        .iter()
+        .any(|&p| p > 0.0)
+}
+
+// Variation of an upsampled sentence. Each augmentation fires independently with its configured
+// probability; a variation that ends up empty falls back to the original sentence.
+pub fn augment_sentence(sentence: &str, config: &TrainingConfig, rng: &mut impl Rng) -> String {
+    let mut chars: Vec<char> = sentence.chars().collect();
+    let mut fires = |p: f32| p > 0.0 && rng.random::<f32>() < p;
+    let (crop, case, punctuation, digits, noise) = (
+        fires(config.augment_crop),
+        fires(config.augment_case),
+        fires(config.augment_punctuation),
+        fires(config.augment_digits),
+        fires(config.augment_noise),
+    );
+
+    if crop {
+        chars = crop_chars(&chars, rng);
+    }
+    if case {
+        chars = change_case(&chars, rng);
+    }
+    if punctuation {
+        if rng.random::<bool>() {
+            chars.retain(|&c| !is_punctuation(c));
+        } else {
+            for _ in 0..rng.random_range(1..=3) {
+                let position = rng.random_range(0..=chars.len());
+                chars.insert(position, *INSERTED_PUNCTUATION.choose(rng).unwrap());
+            }
+        }
+    }
+    if digits {
+        inject_number(&mut chars, rng);
+    }
+    if noise {
+        add_char_noise(&mut chars, rng);
+    }
+
+    let augmented: String = chars.into_iter().collect();
+    if augmented.trim().is_empty() { sentence.to_string() } else { augmented }
+}
+
+// Keep a random window of at least half the sentence: whole words when there are several,
+// otherwise characters
+fn crop_chars(chars: &[char], rng: &mut impl Rng) -> Vec<char> {
+    let text: String = chars.iter().collect();
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.len() >= 2 {
+        let keep = rng.random_range(words.len().div_ceil(2)..=words.len());
+        let start = rng.random_range(0..=words.len() - keep);
+        return words[start..start + keep].join(" ").chars().collect();
+    }
+    if chars.len() < 2 {
+        return chars.to_vec();
+    }
+    let keep = rng.random_range(chars.len().div_ceil(2)..=chars.len());
+    let start = rng.random_range(0..=chars.len() - keep);
+    chars[start..start + keep].to_vec()
+}
+
+// Lowercase, uppercase, or capitalize the first letter of every word
+fn change_case(chars: &[char], rng: &mut impl Rng) -> Vec<char> {
+    match rng.random_range(0..3) {
+        0 => chars.iter().flat_map(|c| c.to_lowercase()).collect(),
+        1 => chars.iter().flat_map(|c| c.to_uppercase()).collect(),
+        _ => {
+            let mut result = Vec::with_capacity(chars.len());
+            let mut word_start = true;
+            for &c in chars {
+                if word_start {
+                    result.extend(c.to_uppercase());
+                } else {
+                    result.push(c);
+                }
+                word_start = c.is_whitespace();
+            }
+            result
+        }
+    }
+}
+
+// Insert a 1-4 digit number as its own word at a random word boundary
+fn inject_number(chars: &mut Vec<char>, rng: &mut impl Rng) {
+    let digits = rng.random_range(1..=4);
+    let number: Vec<char> = (0..digits)
+        .map(|_| char::from_digit(rng.random_range(0..10), 10).unwrap())
+        .collect();
+    let boundaries: Vec<usize> = (0..=chars.len())
+        .filter(|&i| i == 0 || i == chars.len() || chars[i - 1].is_whitespace())
+        .collect();
+    let position = *boundaries.choose(rng).unwrap();
+
+    let mut inserted = number;
+    if position < chars.len() {
+        inserted.push(' ');
+    }
+    if position == chars.len() && position > 0 && !chars[position - 1].is_whitespace() {
+        inserted.insert(0, ' ');
+    }
+    chars.splice(position..position, inserted);
+}
+
+// Typo-like edits: delete, duplicate or swap adjacent characters, about one per 50 characters
+fn add_char_noise(chars: &mut Vec<char>, rng: &mut impl Rng) {
+    let edits = 1 + chars.len() / 50;
+    for _ in 0..edits {
+        if chars.len() < 2 {
+            return;
+        }
+        let position = rng.random_range(0..chars.len() - 1);
+        match rng.random_range(0..3) {
+            0 => {
+                chars.remove(position);
+            }
+            1 => chars.insert(position, chars[position]),
+            _ => chars.swap(position, position + 1),
+        }
+    }
+}
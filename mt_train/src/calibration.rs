@@ -0,0 +1,231 @@
+use serde::{Deserialize, Serialize};
+
+use crate::features::{FeatureMatrix, UNKNOWN_LABEL};
+use crate::LanguageDetectorTrainer;
+
+// Number of equal-width confidence bins for ECE and the reliability diagram
+const CALIBRATION_BINS: usize = 10;
+// Temperatures are searched in [1/20, 20]
+const MAX_LOG_TEMPERATURE: f32 = 3.0;
+// Coordinate-descent passes over the languages for per-language temperatures
+const PER_LANGUAGE_PASSES: usize = 3;
+
+// Post-training temperature scaling of the softmax, fitted on the validation split
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Calibration {
+    None,
+    // One temperature shared by every language
+    Global,
+    // One temperature per language, dividing that language's score
+    PerLanguage,
+}
+
+// Confidence bin of the reliability diagram
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReliabilityBin {
+    pub count: usize,
+    pub mean_confidence: f32,
+    pub accuracy: f32,
+}
+
+// Calibration quality of a set of predictions
+#[derive(Debug, Clone)]
+pub struct CalibrationStats {
+    pub loss: f32,
+    pub expected_calibration_error: f32,
+    pub bins: Vec<ReliabilityBin>,
+}
+
+// Softmax of scores divided by per-language temperatures
+pub fn tempered_softmax(scores: &[f32], temperatures: &[f32]) -> Vec<f32> {
+    let scaled: Vec<f32> = scores.iter().zip(temperatures).map(|(&score, &t)| score / t).collect();
+    LanguageDetectorTrainer::softmax(&scaled)
+}
+
+// Average cross-entropy of the labeled rows under the given temperatures
+fn loss(rows: &[(Vec<f32>, usize)], temperatures: &[f32]) -> f32 {
+    let total: f64 = rows.iter()
+        .map(|(scores, target)| -(tempered_softmax(scores, temperatures)[*target].max(1e-10) as f64).ln())
+        .sum();
+    (total / rows.len().max(1) as f64) as f32
+}
+
+// Minimize a unimodal function of the log temperature by golden-section search
+fn golden_section(mut f: impl FnMut(f32) -> f32) -> f32 {
+    let ratio = (5f32.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = (-MAX_LOG_TEMPERATURE, MAX_LOG_TEMPERATURE);
+    let mut x1 = high - ratio * (high - low);
+    let mut x2 = low + ratio * (high - low);
+    let (mut f1, mut f2) = (f(x1), f(x2));
+    for _ in 0..40 {
+        if f1 < f2 {
+            high = x2;
+            x2 = x1;
+            f2 = f1;
+            x1 = high - ratio * (high - low);
+            f1 = f(x1);
+        } else {
+            low = x1;
+            x1 = x2;
+            f1 = f2;
+            x2 = low + ratio * (high - low);
+            f2 = f(x2);
+        }
+    }
+    (low + high) / 2.0
+}
+
+// Best temperature for one language with the others fixed. Each row's softmax denominator is
+// split into the fixed part (kept as a shifted sum) and the language's own term, so every
+// evaluation of the search is a single pass over the rows.
+fn fit_language_temperature(rows: &[(Vec<f32>, usize)], temperatures: &[f32], lang_idx: usize) -> f32 {
+    struct RowTerms {
+        shift: f64,
+        rest: f64,
+        score: f64,
+        // Scaled target score, or None when the target is lang_idx itself
+        target_logit: Option<f64>,
+    }
+    let terms: Vec<RowTerms> = rows.iter()
+        .map(|(scores, target)| {
+            let scaled = scores.iter().zip(temperatures).enumerate()
+                .filter(|&(idx, _)| idx != lang_idx)
+                .map(|(_, (&score, &t))| score as f64 / t as f64);
+            let shift = scaled.clone().fold(f64::MIN, f64::max).max(-1e30);
+            RowTerms {
+                shift,
+                rest: scaled.map(|logit| (logit - shift).exp()).sum(),
+                score: scores[lang_idx] as f64,
+                target_logit: (*target != lang_idx).then(|| scores[*target] as f64 / temperatures[*target] as f64),
+            }
+        })
+        .collect();
+
+    golden_section(|log_t| {
+        let temperature = (log_t as f64).exp();
+        let total: f64 = terms.iter()
+            .map(|row| {
+                let logit = row.score / temperature;
+                let max = row.shift.max(logit);
+                let log_denominator = (row.rest * (row.shift - max).exp() + (logit - max).exp()).ln() + max;
+                log_denominator - row.target_logit.unwrap_or(logit)
+            })
+            .sum();
+        (total / terms.len().max(1) as f64) as f32
+    }).exp()
+}
+
+// Loss, ECE and reliability bins (by top-1 confidence) under the given temperatures
+pub fn calibration_stats(rows: &[(Vec<f32>, usize)], temperatures: &[f32]) -> CalibrationStats {
+    let mut counts = [0usize; CALIBRATION_BINS];
+    let mut confidence_sums = [0f64; CALIBRATION_BINS];
+    let mut correct = [0usize; CALIBRATION_BINS];
+    for (scores, target) in rows {
+        let probabilities = tempered_softmax(scores, temperatures);
+        let (predicted, confidence) = probabilities.iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(idx, &p)| (idx, p))
+            .unwrap_or((0, 0.0));
+        let bin = ((confidence * CALIBRATION_BINS as f32) as usize).min(CALIBRATION_BINS - 1);
+        counts[bin] += 1;
+        confidence_sums[bin] += confidence as f64;
+        if predicted == *target {
+            correct[bin] += 1;
+        }
+    }
+
+    let total = rows.len().max(1) as f32;
+    let bins: Vec<ReliabilityBin> = (0..CALIBRATION_BINS)
+        .map(|bin| match counts[bin] {
+            0 => ReliabilityBin::default(),
+            count => ReliabilityBin {
+                count,
+                mean_confidence: (confidence_sums[bin] / count as f64) as f32,
+                accuracy: correct[bin] as f32 / count as f32,
+            },
+        })
+        .collect();
+    let expected_calibration_error = bins.iter()
+        .map(|bin| bin.count as f32 / total * (bin.accuracy - bin.mean_confidence).abs())
+        .sum();
+    CalibrationStats {
+        loss: loss(rows, temperatures),
+        expected_calibration_error,
+        bins,
+    }
+}
+
+pub fn print_reliability(label: &str, stats: &CalibrationStats) {
+    println!("  {}: NLL = {:.4}, ECE = {:.4}", label, stats.loss, stats.expected_calibration_error);
+    println!("    {:>11} {:>8} {:>11} {:>9}", "confidence", "count", "mean conf", "accuracy");
+    for (bin, stats) in stats.bins.iter().enumerate() {
+        let low = bin as f32 / CALIBRATION_BINS as f32;
+        let high = (bin + 1) as f32 / CALIBRATION_BINS as f32;
+        if stats.count == 0 {
+            println!("    {:>4.1}-{:<6.1} {:>8} {:>11} {:>9}", low, high, 0, "-", "-");
+        } else {
+            println!("    {:>4.1}-{:<6.1} {:>8} {:>11.4} {:>9.4}", low, high, stats.count, stats.mean_confidence, stats.accuracy);
+        }
+    }
+}
+
+impl LanguageDetectorTrainer {
+    // Raw scores and targets of the labeled rows with features
+    fn calibration_rows(&self, features: &FeatureMatrix) -> Vec<(Vec<f32>, usize)> {
+        (0..features.len())
+            .filter_map(|row| {
+                let (buckets, values) = features.row(row);
+                let target = features.labels[row];
+                (target != UNKNOWN_LABEL && !buckets.is_empty())
+                    .then(|| (self.predict_row(buckets, values), target as usize))
+            })
+            .collect()
+    }
+
+    // Fit temperatures that minimize the validation loss and report calibration before and after.
+    // Per-language temperatures start from the global fit and are refined one language at a time.
+    pub fn calibrate(&mut self, validation: &FeatureMatrix, calibration: Calibration) {
+        let num_languages = self.language_codes.len();
+        self.temperatures = vec![1.0; num_languages];
+        if calibration == Calibration::None {
+            return;
+        }
+        let rows = self.calibration_rows(validation);
+        if rows.is_empty() {
+            println!("\nValidation split is empty, skipping calibration");
+            return;
+        }
+
+        let before = calibration_stats(&rows, &self.temperatures);
+        let global = golden_section(|log_t| loss(&rows, &vec![log_t.exp(); num_languages])).exp();
+        let mut temperatures = vec![global; num_languages];
+        if calibration == Calibration::PerLanguage {
+            for _ in 0..PER_LANGUAGE_PASSES {
+                for lang_idx in 0..num_languages {
+                    temperatures[lang_idx] = fit_language_temperature(&rows, &temperatures, lang_idx);
+                }
+            }
+        }
+        self.temperatures = temperatures;
+        let after = calibration_stats(&rows, &self.temperatures);
+
+        println!("\nCalibration on {} validation examples:", rows.len());
+        match calibration {
+            Calibration::PerLanguage => {
+                let (min, max) = self.temperatures.iter()
+                    .fold((f32::MAX, f32::MIN), |(min, max), &t| (min.min(t), max.max(t)));
+                println!("  Per-language temperatures from {:.4} to {:.4} (global fit {:.4})", min, max, global);
+            }
+            _ => println!("  Temperature = {:.4}", global),
+        }
+        print_reliability("Before", &before);
+        print_reliability("After", &after);
+    }
+
+    // Softmax probabilities with the fitted temperatures applied
+    pub fn calibrated_probabilities(&self, scores: &[f32]) -> Vec<f32> {
+        tempered_softmax(scores, &self.temperatures)
+    }
+}
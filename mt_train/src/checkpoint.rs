@@ -0,0 +1,161 @@
+use std::error::Error;
+
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::model::{read_container, write_container, Container, HashParams, Words};
+use crate::schedule::PlateauState;
+use crate::{LanguageDetectorTrainer, Objective, TrainingConfig, UpdateMode};
+
+const CHECKPOINT_MAGIC: &[u8; 4] = b"WLTC";
+pub const CHECKPOINT_FORMAT_VERSION: u32 = 8;
+
+// Where and how often train() writes checkpoints
+#[derive(Debug, Clone)]
+pub struct CheckpointOptions {
+    pub path: String,
+    pub every_epochs: usize,
+    // Continue from the checkpoint at `path` if it exists
+    pub resume: bool,
+}
+
+// Training loop state needed to continue exactly where a run stopped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrainingState {
+    pub next_epoch: usize,
+    // Best early stopping score so far and the epoch (1-based) it was reached
+    pub best_score: Option<f32>,
+    pub best_epoch: usize,
+    pub patience_counter: usize,
+    pub shuffle_rng: ChaCha8Rng,
+    // Draws short-text windows when window_mode is not sentence
+    pub window_rng: ChaCha8Rng,
+    pub plateau: PlateauState,
+    // Stored as arrays after the header
+    #[serde(skip)]
+    pub best_weights: Vec<f32>,
+    #[serde(skip)]
+    pub best_intercepts: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CheckpointHeader {
+    language_codes: Vec<String>,
+    config: TrainingConfig,
+    hash_params: HashParams,
+    state: TrainingState,
+    // Optimizer update steps taken; per-parameter optimizer state follows the weight arrays
+    optimizer_steps: u64,
+    // Group DRO loss estimates; the language weights are derived from them
+    group_losses: Vec<Option<f32>>,
+    // Resolved thread count; mini-batch results depend on it, so num_threads = 0 is not enough
+    threads: usize,
+}
+
+impl LanguageDetectorTrainer {
+    pub fn save_checkpoint(&self, checkpoint_file: &str, state: &TrainingState) -> Result<(), Box<dyn Error>> {
+        let header = CheckpointHeader {
+            language_codes: self.language_codes.clone(),
+            config: self.config.clone(),
+            hash_params: HashParams::current(),
+            state: state.clone(),
+            optimizer_steps: self.optimizer.step_count(),
+            group_losses: self.group_losses.clone(),
+            threads: self.config.thread_count(),
+        };
+        let mut arrays: Vec<Words> = [&self.weights, &self.intercepts, &state.best_weights, &state.best_intercepts]
+            .map(|array| Words::F32(array))
+            .into();
+        arrays.extend(self.optimizer.state_arrays().into_iter().map(Words::F32));
+        write_container(checkpoint_file, CHECKPOINT_MAGIC, CHECKPOINT_FORMAT_VERSION, &header, &arrays)?;
+        println!("Checkpoint saved to {} (epoch {})", checkpoint_file, state.next_epoch);
+        Ok(())
+    }
+
+    // Restore weights and intercepts from a checkpoint and return the loop state.
+    // The checkpoint must come from a run with the same languages and config.
+    pub fn load_checkpoint(&mut self, checkpoint_file: &str) -> Result<TrainingState, Box<dyn Error>> {
+        let (header, arrays): Container<CheckpointHeader> =
+            read_container(checkpoint_file, CHECKPOINT_MAGIC, CHECKPOINT_FORMAT_VERSION)?;
+
+        if header.hash_params != HashParams::current() {
+            return Err(format!("{}: checkpoint uses different feature hash parameters", checkpoint_file).into());
+        }
+        if header.language_codes != self.language_codes {
+            return Err(format!("{}: checkpoint was trained on different languages", checkpoint_file).into());
+        }
+        // The epoch count may be raised to extend a finished run; everything else must match
+        if (TrainingConfig { epochs: self.config.epochs, ..header.config.clone() }) != self.config {
+            return Err(format!("{}: checkpoint config {:?} differs from the requested config {:?}",
+                checkpoint_file, header.config, self.config).into());
+        }
+        if self.config.update_mode == UpdateMode::MiniBatch && header.threads != self.config.thread_count() {
+            return Err(format!("{}: checkpoint computed mini-batch gradients on {} threads, this run uses {}; set --num-threads {} to resume",
+                checkpoint_file, header.threads, self.config.thread_count(), header.threads).into());
+        }
+        if header.group_losses.len() != self.language_codes.len() {
+            return Err(format!("{}: group DRO losses do not match the languages", checkpoint_file).into());
+        }
+        if arrays.len() < 4 {
+            return Err(format!("{}: expected current and best weight and intercept arrays", checkpoint_file).into());
+        }
+        let mut optimizer_state = arrays;
+        let [weights, intercepts, best_weights, best_intercepts]: [Vec<f32>; 4] = optimizer_state.drain(..4)
+            .collect::<Vec<_>>()
+            .try_into()
+            .expect("length checked above");
+        if weights.len() != self.weights.len() || best_weights.len() != self.weights.len()
+            || intercepts.len() != self.intercepts.len() || best_intercepts.len() != self.intercepts.len() {
+            return Err(format!("{}: weight shape does not match the model", checkpoint_file).into());
+        }
+
+        self.optimizer.restore(optimizer_state, header.optimizer_steps)
+            .map_err(|e| format!("{}: {}", checkpoint_file, e))?;
+        self.weights = weights;
+        self.intercepts = intercepts;
+        self.group_losses = header.group_losses;
+        if self.config.objective == Objective::GroupDro {
+            self.refresh_group_weights();
+        }
+        let mut state = header.state;
+        state.best_weights = best_weights;
+        state.best_intercepts = best_intercepts;
+        println!("Resuming from {} at epoch {}", checkpoint_file, state.next_epoch + 1);
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::OptimizerKind;
+    use crate::testing::{examples, quick_config, temp_path, trainer};
+    use crate::TrainOptions;
+
+    // Training N epochs straight must match N/2 epochs, a checkpoint and a resume
+    #[test]
+    fn resume_matches_uninterrupted_run() {
+        for (optimizer, update_mode) in [(OptimizerKind::Sgd, UpdateMode::PerExample), (OptimizerKind::Adam, UpdateMode::MiniBatch)] {
+            let config = TrainingConfig { optimizer, update_mode, batch_size: 8, num_threads: 1, ..quick_config() };
+            let mut straight = trainer(config.clone());
+            straight.train(&examples(), &TrainOptions::default()).unwrap();
+
+            let path = temp_path(&format!("resume_{:?}.ckpt", optimizer));
+            let options = |resume| TrainOptions {
+                checkpoint: Some(CheckpointOptions { path: path.clone(), every_epochs: config.epochs / 2, resume }),
+                ..TrainOptions::default()
+            };
+            trainer(TrainingConfig { epochs: config.epochs / 2, ..config.clone() })
+                .train(&examples(), &options(false))
+                .unwrap();
+            assert!(std::path::Path::new(&path).exists(), "no checkpoint written");
+            let mut resumed = trainer(config.clone());
+            resumed.train(&examples(), &options(true)).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(resumed.weights, straight.weights, "{:?} {:?}", optimizer, update_mode);
+            assert_eq!(resumed.intercepts, straight.intercepts, "{:?} {:?}", optimizer, update_mode);
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use clap::{Args, Parser, Subcommand};
+
+use crate::{BalanceStrategy, Objective, TrainingConfig, UpdateMode};
+use crate::calibration::Calibration;
+use crate::external::DataFormat;
+use crate::metrics::StoppingMetric;
+use crate::optimizer::OptimizerKind;
+use crate::regularization::{Penalty, RegularizationMode};
+use crate::schedule::LrSchedule;
+use crate::window::WindowMode;
+
+// Command-line interface for the trainer binary
+#[derive(Debug, Parser)]
+#[command(name = "mt_train", about = "Train and inspect the egalitarian language detector")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Train a new model from a labeled CSV dataset
+    Train(Box<TrainArgs>),
+    /// Evaluate a saved model on a labeled CSV, TSV or JSONL dataset
+    Eval(EvalArgs),
+    /// Export a saved model's weights to a C++ header
+    Export(ExportArgs),
+    /// Predict the language of text given as arguments or on stdin
+    Predict(PredictArgs),
+}
+
+// Paths and config flags override the experiment file, which overrides the built-in defaults
+#[derive(Debug, Args)]
+pub struct TrainArgs {
+    /// Experiment file (.toml or .json) with paths, languages and config
+    #[arg(long)]
+    pub experiment: Option<String>,
+
+    /// Training sentences (CSV with id, lan_code, sentence columns) [default: ../dataset/sentences.csv]
+    #[arg(long)]
+    pub data: Option<String>,
+
+    /// JSON map from language code to language name [default: ../dataset/lan_to_language.json]
+    #[arg(long)]
+    pub language_names: Option<String>,
+
+    /// Comma-separated allowlist of language codes to train on
+    #[arg(long, value_delimiter = ',')]
+    pub languages: Vec<String>,
+
+    /// Where to write the trained model [default: model.bin]
+    #[arg(long)]
+    pub model: Option<String>,
+
+    /// Continue training from a saved model instead of a fresh initialization
+    #[arg(long)]
+    pub init_model: Option<String>,
+
+    /// Where to write the exported C++ weights header; repeatable [default: weights_balanced.rs]
+    #[arg(long)]
+    pub export: Vec<String>,
+
+    /// Write periodic training checkpoints to this file
+    #[arg(long)]
+    pub checkpoint: Option<String>,
+
+    /// Epochs between checkpoints
+    #[arg(long, default_value_t = 10)]
+    pub checkpoint_every: usize,
+
+    /// Continue from the checkpoint file if it exists
+    #[arg(long, requires = "checkpoint")]
+    pub resume: bool,
+
+    /// Directory for cached feature matrices, reused by runs with the same data, dimension and hash seed
+    #[arg(long)]
+    pub feature_cache: Option<String>,
+
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct EvalArgs {
+    /// Saved model to evaluate
+    #[arg(long, default_value = "model.bin")]
+    pub model: String,
+
+    /// Labeled sentences (CSV, TSV or JSONL; format guessed from the extension)
+    #[arg(long, default_value = "../dataset/sentences.csv")]
+    pub data: String,
+
+    /// Format of --data, overriding the extension
+    #[arg(long, value_enum)]
+    pub format: Option<DataFormat>,
+
+    /// Column (header name or 0-based index) or JSON field holding the language label
+    #[arg(long, default_value = "lan_code")]
+    pub label_column: String,
+
+    /// Column or JSON field holding the text
+    #[arg(long, default_value = "sentence")]
+    pub text_column: String,
+
+    /// Column or JSON field holding a numeric id; rows without one are numbered
+    #[arg(long, default_value = "id")]
+    pub id_column: String,
+
+    /// The CSV/TSV file has no header row (columns must be indices)
+    #[arg(long)]
+    pub no_header: bool,
+
+    /// Map file labels to model languages: JSON object or two-column CSV/TSV (from, to).
+    /// Labels the model does not know after mapping are ignored.
+    #[arg(long)]
+    pub label_map: Option<String>,
+
+    /// Part of the model's train/validation/test split to evaluate on. Defaults to `test` when the
+    /// file is the data the model was trained on, and to `all` for any other file.
+    #[arg(long, value_enum)]
+    pub split: Option<EvalSplit>,
+
+    /// Also report accuracy on word or character windows of the model's window lengths
+    /// (defaults to the window mode the model was trained with)
+    #[arg(long, value_enum)]
+    pub window_mode: Option<WindowMode>,
+
+    /// Write per-language precision, recall, F1 and support to this file (.json or .csv)
+    #[arg(long)]
+    pub scores_output: Option<String>,
+
+    /// Values of k for top-k accuracy (comma-separated, each at least 1)
+    #[arg(long, value_delimiter = ',', default_value = "1,3,5", value_parser = parse_top_k)]
+    pub top_k: Vec<usize>,
+
+    /// Write the confusion matrix to this CSV file
+    #[arg(long)]
+    pub confusion_output: Option<String>,
+
+    /// Write every misclassified example to this JSONL (or .csv) file
+    #[arg(long)]
+    pub errors_output: Option<String>,
+
+    /// Number of most confused language pairs to list with example sentences
+    #[arg(long, default_value_t = 10)]
+    pub top_confused: usize,
+}
+
+// Part of the data `eval` scores
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EvalSplit {
+    All,
+    Train,
+    Validation,
+    Test,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Saved model to export
+    #[arg(long, default_value = "model.bin")]
+    pub model: String,
+
+    /// Where to write the C++ weights header
+    #[arg(long, default_value = "weights_balanced.rs")]
+    pub output: String,
+}
+
+#[derive(Debug, Args)]
+pub struct PredictArgs {
+    /// Saved model to predict with
+    #[arg(long, default_value = "model.bin")]
+    pub model: String,
+
+    /// Number of candidate languages to print per input
+    #[arg(long, default_value_t = 1)]
+    pub top: usize,
+
+    /// Texts to classify; reads one text per line from stdin when empty
+    pub text: Vec<String>,
+}
+
+// Overrides for every TrainingConfig field; unset flags keep the base value
+#[derive(Debug, Args)]
+pub struct ConfigArgs {
+    /// Base learning rate
+    #[arg(long)]
+    pub learning_rate: Option<f32>,
+    #[arg(long, value_enum)]
+    pub lr_schedule: Option<LrSchedule>,
+    /// Decay factor for the step, exponential and plateau schedules
+    #[arg(long)]
+    pub lr_decay_factor: Option<f32>,
+    /// Epochs between decays for the step schedule
+    #[arg(long)]
+    pub lr_step_epochs: Option<usize>,
+    /// Stalled validation epochs before the plateau schedule decays
+    #[arg(long)]
+    pub lr_plateau_patience: Option<usize>,
+    /// Lowest rate for the cosine and plateau schedules
+    #[arg(long)]
+    pub lr_min: Option<f32>,
+    /// Epochs of linear warmup
+    #[arg(long)]
+    pub warmup_epochs: Option<usize>,
+    #[arg(long, value_enum)]
+    pub optimizer: Option<OptimizerKind>,
+    /// Momentum coefficient for the momentum optimizer
+    #[arg(long)]
+    pub momentum: Option<f32>,
+    #[arg(long)]
+    pub adam_beta1: Option<f32>,
+    #[arg(long)]
+    pub adam_beta2: Option<f32>,
+    /// Denominator guard for AdaGrad and Adam
+    #[arg(long)]
+    pub epsilon: Option<f32>,
+    #[arg(long)]
+    pub epochs: Option<usize>,
+    #[arg(long)]
+    pub regularization: Option<f32>,
+    /// Weight penalty; l1 and elastic-net drive weights exactly to zero
+    #[arg(long, value_enum)]
+    pub penalty: Option<Penalty>,
+    /// Share of the penalty applied as L1 for elastic-net
+    #[arg(long)]
+    pub l1_ratio: Option<f32>,
+    /// Penalize every weight on each update (lazy) or only the buckets in the update (touched)
+    #[arg(long, value_enum)]
+    pub regularization_mode: Option<RegularizationMode>,
+    /// Number of feature hash buckets
+    #[arg(long)]
+    pub dimension: Option<usize>,
+    /// Fraction of each language's unique sentences used for training, e.g. 0.8
+    #[arg(long)]
+    pub train_test_split: Option<f32>,
+    #[arg(long)]
+    pub batch_size: Option<usize>,
+    /// Update after every example or once per mini-batch
+    #[arg(long, value_enum)]
+    pub update_mode: Option<UpdateMode>,
+    /// Threads for mini-batch gradients (0 = all cores); results are reproducible for a fixed count
+    #[arg(long)]
+    pub num_threads: Option<usize>,
+    #[arg(long)]
+    pub early_stopping_patience: Option<usize>,
+    /// Validation metric watched by early stopping
+    #[arg(long, value_enum)]
+    pub early_stopping_metric: Option<StoppingMetric>,
+    /// Fraction of each language's training sentences held out for validation
+    #[arg(long)]
+    pub validation_split: Option<f32>,
+    /// Resample every language to samples_per_language, or train on all data with class weights
+    #[arg(long, value_enum)]
+    pub balance_strategy: Option<BalanceStrategy>,
+    /// Sentences per language with the resample strategy
+    #[arg(long)]
+    pub samples_per_language: Option<usize>,
+    /// Probability of cropping an upsampled copy to a random substring
+    #[arg(long)]
+    pub augment_crop: Option<f32>,
+    /// Probability of changing the casing of an upsampled copy
+    #[arg(long)]
+    pub augment_case: Option<f32>,
+    /// Probability of stripping or inserting punctuation in an upsampled copy
+    #[arg(long)]
+    pub augment_punctuation: Option<f32>,
+    /// Probability of injecting a number into an upsampled copy
+    #[arg(long)]
+    pub augment_digits: Option<f32>,
+    /// Probability of character-level typos in an upsampled copy
+    #[arg(long)]
+    pub augment_noise: Option<f32>,
+    /// Train on random word or character windows instead of whole sentences
+    #[arg(long, value_enum)]
+    pub window_mode: Option<WindowMode>,
+    /// Shortest window, in words or characters
+    #[arg(long)]
+    pub window_min: Option<usize>,
+    /// Longest window, in words or characters
+    #[arg(long)]
+    pub window_max: Option<usize>,
+    /// Share of training examples replaced by a window each epoch
+    #[arg(long)]
+    pub window_probability: Option<f32>,
+    /// Fit a softmax temperature (global or per language) on the validation split after training
+    #[arg(long, value_enum)]
+    pub calibration: Option<Calibration>,
+    /// Exponent of the inverse-frequency class weights (0 = unweighted, 1 = fully balanced)
+    #[arg(long)]
+    pub class_weight_power: Option<f32>,
+    /// Minimize the average loss, or the worst languages' loss with group DRO
+    #[arg(long, value_enum)]
+    pub objective: Option<Objective>,
+    /// Group DRO sharpness: how strongly language weights favor high estimated losses
+    #[arg(long)]
+    pub dro_step_size: Option<f32>,
+    /// Seed for every random choice; equal seeds give identical models
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+impl ConfigArgs {
+    pub fn apply(&self, config: &mut TrainingConfig) {
+        if let Some(v) = self.learning_rate { config.learning_rate = v; }
+        if let Some(v) = self.lr_schedule { config.lr_schedule = v; }
+        if let Some(v) = self.lr_decay_factor { config.lr_decay_factor = v; }
+        if let Some(v) = self.lr_step_epochs { config.lr_step_epochs = v; }
+        if let Some(v) = self.lr_plateau_patience { config.lr_plateau_patience = v; }
+        if let Some(v) = self.lr_min { config.lr_min = v; }
+        if let Some(v) = self.warmup_epochs { config.warmup_epochs = v; }
+        if let Some(v) = self.optimizer { config.optimizer = v; }
+        if let Some(v) = self.momentum { config.momentum = v; }
+        if let Some(v) = self.adam_beta1 { config.adam_beta1 = v; }
+        if let Some(v) = self.adam_beta2 { config.adam_beta2 = v; }
+        if let Some(v) = self.epsilon { config.epsilon = v; }
+        if let Some(v) = self.epochs { config.epochs = v; }
+        if let Some(v) = self.regularization { config.regularization = v; }
+        if let Some(v) = self.penalty { config.penalty = v; }
+        if let Some(v) = self.l1_ratio { config.l1_ratio = v; }
+        if let Some(v) = self.regularization_mode { config.regularization_mode = v; }
+        if let Some(v) = self.dimension { config.dimension = v; }
+        if let Some(v) = self.train_test_split { config.train_test_split = v; }
+        if let Some(v) = self.batch_size { config.batch_size = v; }
+        if let Some(v) = self.update_mode { config.update_mode = v; }
+        if let Some(v) = self.num_threads { config.num_threads = v; }
+        if let Some(v) = self.early_stopping_patience { config.early_stopping_patience = v; }
+        if let Some(v) = self.early_stopping_metric { config.early_stopping_metric = v; }
+        if let Some(v) = self.validation_split { config.validation_split = v; }
+        if let Some(v) = self.balance_strategy { config.balance_strategy = v; }
+        if let Some(v) = self.samples_per_language { config.samples_per_language = v; }
+        if let Some(v) = self.augment_crop { config.augment_crop = v; }
+        if let Some(v) = self.augment_case { config.augment_case = v; }
+        if let Some(v) = self.augment_punctuation { config.augment_punctuation = v; }
+        if let Some(v) = self.augment_digits { config.augment_digits = v; }
+        if let Some(v) = self.augment_noise { config.augment_noise = v; }
+        if let Some(v) = self.window_mode { config.window_mode = v; }
+        if let Some(v) = self.window_min { config.window_min = v; }
+        if let Some(v) = self.window_max { config.window_max = v; }
+        if let Some(v) = self.window_probability { config.window_probability = v; }
+        if let Some(v) = self.calibration { config.calibration = v; }
+        if let Some(v) = self.class_weight_power { config.class_weight_power = v; }
+        if let Some(v) = self.objective { config.objective = v; }
+        if let Some(v) = self.dro_step_size { config.dro_step_size = v; }
+        if let Some(v) = self.seed { config.seed = v; }
+    }
+}
+
+// Top-k accuracy with k = 0 is meaningless, so reject it before evaluation starts
+fn parse_top_k(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("k must be at least 1".to_string()),
+        Ok(k) => Ok(k),
+        Err(e) => Err(e.to_string()),
+    }
+}
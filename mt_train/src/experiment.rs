@@ -0,0 +1,49 @@
+use std::error::Error;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::TrainingConfig;
+
+// A reproducible experiment: dataset paths, language allowlist, export targets and config.
+// Loaded from TOML or JSON; relative paths are resolved against the file's directory.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Experiment {
+    pub data: Option<String>,
+    pub language_names: Option<String>,
+    // Empty means every language found in the data
+    #[serde(default)]
+    pub languages: Vec<String>,
+    pub model: Option<String>,
+    #[serde(default)]
+    pub export: Vec<String>,
+    #[serde(default)]
+    pub config: TrainingConfig,
+}
+
+impl Experiment {
+    pub fn load(file_path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = std::fs::read_to_string(file_path)?;
+        let mut experiment: Experiment = match Path::new(file_path).extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&content).map_err(|e| format!("{}: {}", file_path, e))?,
+            Some("json") => serde_json::from_str(&content).map_err(|e| format!("{}: {}", file_path, e))?,
+            _ => return Err(format!("{}: experiment file must end in .toml or .json", file_path).into()),
+        };
+
+        let base_dir = Path::new(file_path).parent().unwrap_or(Path::new(""));
+        let resolve = |path: &mut String| {
+            if Path::new(path.as_str()).is_relative() {
+                *path = base_dir.join(&*path).to_string_lossy().into_owned();
+            }
+        };
+        experiment.data.iter_mut()
+            .chain(experiment.language_names.iter_mut())
+            .chain(experiment.model.iter_mut())
+            .chain(experiment.export.iter_mut())
+            .for_each(resolve);
+
+        println!("Loaded experiment from {}", file_path);
+        Ok(experiment)
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::BufRead;
+
+use crate::TrainingExample;
+
+// Layout of a labeled evaluation file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DataFormat {
+    Csv,
+    Tsv,
+    // One JSON object per line
+    Jsonl,
+}
+
+impl DataFormat {
+    // Guess from the file extension, defaulting to CSV
+    pub fn from_path(path: &str) -> Self {
+        let lower = path.to_lowercase();
+        if lower.ends_with(".tsv") || lower.ends_with(".tab") {
+            DataFormat::Tsv
+        } else if lower.ends_with(".jsonl") || lower.ends_with(".ndjson") {
+            DataFormat::Jsonl
+        } else {
+            DataFormat::Csv
+        }
+    }
+}
+
+// Where to find the label, text and (optional) id of each row. Columns are header names,
+// or 0-based indices for files without a header row.
+#[derive(Debug, Clone)]
+pub struct LabeledFileOptions {
+    pub format: DataFormat,
+    pub label_column: String,
+    pub text_column: String,
+    pub id_column: String,
+    pub has_header: bool,
+}
+
+// Load labeled examples from a CSV, TSV or JSONL file. Rows without an id (or with a
+// non-numeric one) are numbered by their position in the file.
+pub fn load_labeled_file(path: &str, options: &LabeledFileOptions) -> Result<Vec<TrainingExample>, Box<dyn Error>> {
+    let examples = match options.format {
+        DataFormat::Csv => load_delimited(path, b',', options)?,
+        DataFormat::Tsv => load_delimited(path, b'\t', options)?,
+        DataFormat::Jsonl => load_jsonl(path, options)?,
+    };
+    println!("Loaded {} labeled examples from {}", examples.len(), path);
+    Ok(examples)
+}
+
+fn load_delimited(path: &str, delimiter: u8, options: &LabeledFileOptions) -> Result<Vec<TrainingExample>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(options.has_header)
+        .flexible(true)
+        .quoting(delimiter != b'\t')
+        .from_path(path)?;
+    let headers = if options.has_header { Some(reader.headers()?.clone()) } else { None };
+    let column = |name: &str| -> Option<usize> {
+        headers.as_ref()
+            .and_then(|headers| headers.iter().position(|header| header == name))
+            .or_else(|| name.parse().ok())
+    };
+    let label_idx = column(&options.label_column)
+        .ok_or_else(|| format!("{}: no label column '{}'", path, options.label_column))?;
+    let text_idx = column(&options.text_column)
+        .ok_or_else(|| format!("{}: no text column '{}'", path, options.text_column))?;
+    let id_idx = column(&options.id_column);
+
+    let mut examples = Vec::new();
+    for (row, record) in reader.records().enumerate() {
+        let record = record?;
+        let (Some(label), Some(text)) = (record.get(label_idx), record.get(text_idx)) else {
+            return Err(format!("{}: row {} has too few columns", path, row + 1).into());
+        };
+        examples.push(TrainingExample {
+            id: id_idx.and_then(|idx| record.get(idx)).and_then(|id| id.parse().ok()).unwrap_or(row as u32),
+            lan_code: label.trim().to_string(),
+            sentence: text.to_string(),
+        });
+    }
+    Ok(examples)
+}
+
+fn load_jsonl(path: &str, options: &LabeledFileOptions) -> Result<Vec<TrainingExample>, Box<dyn Error>> {
+    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut examples = Vec::new();
+    for (row, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: serde_json::Value = serde_json::from_str(&line)
+            .map_err(|e| format!("{}: line {}: {}", path, row + 1, e))?;
+        let field = |name: &str| value.get(name).and_then(|v| v.as_str());
+        let (Some(label), Some(text)) = (field(&options.label_column), field(&options.text_column)) else {
+            return Err(format!("{}: line {} lacks string fields '{}' and '{}'",
+                path, row + 1, options.label_column, options.text_column).into());
+        };
+        let id = value.get(&options.id_column)
+            .and_then(|id| id.as_u64().or_else(|| id.as_str().and_then(|id| id.parse().ok())))
+            .and_then(|id| u32::try_from(id).ok())
+            .unwrap_or(row as u32);
+        examples.push(TrainingExample {
+            id,
+            lan_code: label.trim().to_string(),
+            sentence: text.to_string(),
+        });
+    }
+    Ok(examples)
+}
+
+// Label mapping from a JSON object ({"en": "eng"}) or a two-column CSV/TSV file without header
+pub fn load_label_map(path: &str) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    if path.to_lowercase().ends_with(".json") {
+        return Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?);
+    }
+    let delimiter = if DataFormat::from_path(path) == DataFormat::Tsv { b'\t' } else { b',' };
+    let mut reader = csv::ReaderBuilder::new().delimiter(delimiter).has_headers(false).from_path(path)?;
+    let mut map = HashMap::new();
+    for record in reader.records() {
+        let record = record?;
+        if let (Some(from), Some(to)) = (record.get(0), record.get(1)) {
+            map.insert(from.trim().to_string(), to.trim().to_string());
+        }
+    }
+    Ok(map)
+}
+
+// Rename labels through `label_map`, then drop examples whose label the model does not know,
+// printing how many were dropped per label
+pub fn map_labels(examples: Vec<TrainingExample>, label_map: &HashMap<String, String>, language_codes: &[String]) -> Vec<TrainingExample> {
+    let mut ignored: BTreeMap<String, usize> = BTreeMap::new();
+    let mut mapped = 0;
+    let kept: Vec<TrainingExample> = examples.into_iter()
+        .map(|mut example| {
+            if let Some(code) = label_map.get(&example.lan_code) {
+                example.lan_code = code.clone();
+                mapped += 1;
+            }
+            example
+        })
+        .filter(|example| {
+            let known = language_codes.contains(&example.lan_code);
+            if !known {
+                *ignored.entry(example.lan_code.clone()).or_default() += 1;
+            }
+            known
+        })
+        .collect();
+
+    if mapped > 0 {
+        println!("Mapped the labels of {} examples", mapped);
+    }
+    if !ignored.is_empty() {
+        let total: usize = ignored.values().sum();
+        let labels: Vec<String> = ignored.iter().map(|(label, count)| format!("{} ({})", label, count)).collect();
+        println!("Ignoring {} examples whose label the model does not know: {}", total, labels.join(", "));
+    }
+    kept
+}
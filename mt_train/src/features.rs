@@ -0,0 +1,164 @@
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::model::{read_container, write_container, Container, HashParams, Words};
+use crate::{LanguageDetectorTrainer, TrainingExample};
+
+// Cache files use the model container layout with the cache key as header and the
+// row offsets, buckets, values and labels as arrays
+const FEATURE_CACHE_MAGIC: &[u8; 4] = b"WLTF";
+pub const FEATURE_CACHE_FORMAT_VERSION: u32 = 2;
+
+// Label of rows whose language the model does not know
+pub const UNKNOWN_LABEL: u32 = u32::MAX;
+
+// Extracted features of a dataset in compressed sparse row form: row i holds the
+// (bucket, value) pairs buckets[row_offsets[i]..row_offsets[i + 1]] and label labels[i].
+// Rows keep the order of the examples, including rows with no features.
+#[derive(Debug, Default)]
+pub struct FeatureMatrix {
+    pub row_offsets: Vec<u32>,
+    pub buckets: Vec<u32>,
+    pub values: Vec<f32>,
+    pub labels: Vec<u32>,
+}
+
+// Identifies the inputs a cached matrix was built from
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CacheKey {
+    dimension: usize,
+    hash_params: HashParams,
+    language_codes: Vec<String>,
+    // Hash of every (language, sentence) pair in order
+    fingerprint: u64,
+    rows: usize,
+}
+
+// Hash of every (language, sentence) pair in order. The hash may change between Rust releases,
+// so a mismatch means "maybe different data", never "certainly different".
+pub fn fingerprint(examples: &[TrainingExample]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for example in examples {
+        example.lan_code.hash(&mut hasher);
+        example.sentence.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+impl FeatureMatrix {
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    // Buckets and values of one row
+    #[inline]
+    pub fn row(&self, row: usize) -> (&[u32], &[f32]) {
+        let start = self.row_offsets[row] as usize;
+        let end = self.row_offsets[row + 1] as usize;
+        (&self.buckets[start..end], &self.values[start..end])
+    }
+
+    // Extract features for every example (in parallel, keeping example order)
+    pub fn build(trainer: &LanguageDetectorTrainer, examples: &[TrainingExample]) -> Self {
+        let rows: Vec<(u32, Vec<(u32, f32)>)> = examples.par_iter()
+            .map(|example| {
+                let label = trainer.language_codes.iter()
+                    .position(|code| code == &example.lan_code)
+                    .map_or(UNKNOWN_LABEL, |idx| idx as u32);
+                (label, trainer.extract_features(&example.sentence).into_iter().collect())
+            })
+            .collect();
+
+        let mut matrix = FeatureMatrix {
+            row_offsets: Vec::with_capacity(rows.len() + 1),
+            ..Default::default()
+        };
+        matrix.row_offsets.push(0);
+        for (label, features) in rows {
+            for (bucket, value) in features {
+                matrix.buckets.push(bucket);
+                matrix.values.push(value);
+            }
+            matrix.row_offsets.push(u32::try_from(matrix.buckets.len()).expect("feature matrix exceeds u32 entries"));
+            matrix.labels.push(label);
+        }
+        matrix
+    }
+
+    // Reuse the matrix cached at `cache_file` if it was built from the same examples,
+    // dimension, hash parameters and languages; otherwise build it and refresh the cache
+    pub fn load_or_build(trainer: &LanguageDetectorTrainer, examples: &[TrainingExample], cache_file: &str) -> Result<Self, Box<dyn Error>> {
+        let key = Self::cache_key(trainer, examples);
+        if Path::new(cache_file).exists() {
+            match Self::load(cache_file, &key) {
+                Ok(matrix) => {
+                    println!("Loaded {} cached feature rows from {}", matrix.len(), cache_file);
+                    return Ok(matrix);
+                }
+                Err(e) => println!("Rebuilding feature cache: {}", e),
+            }
+        }
+
+        let matrix = Self::build(trainer, examples);
+        matrix.save(cache_file, &key)?;
+        println!("Cached {} feature rows to {}", matrix.len(), cache_file);
+        Ok(matrix)
+    }
+
+    fn cache_key(trainer: &LanguageDetectorTrainer, examples: &[TrainingExample]) -> CacheKey {
+        CacheKey {
+            dimension: trainer.config.dimension,
+            hash_params: HashParams::current(),
+            language_codes: trainer.language_codes.clone(),
+            fingerprint: fingerprint(examples),
+            rows: examples.len(),
+        }
+    }
+
+    fn save(&self, cache_file: &str, key: &CacheKey) -> Result<(), Box<dyn Error>> {
+        let arrays = [
+            Words::U32(&self.row_offsets),
+            Words::U32(&self.buckets),
+            Words::F32(&self.values),
+            Words::U32(&self.labels),
+        ];
+        write_container(cache_file, FEATURE_CACHE_MAGIC, FEATURE_CACHE_FORMAT_VERSION, key, &arrays)
+    }
+
+    fn load(cache_file: &str, expected: &CacheKey) -> Result<Self, Box<dyn Error>> {
+        let (key, arrays): Container<CacheKey, u32> = read_container(cache_file, FEATURE_CACHE_MAGIC, FEATURE_CACHE_FORMAT_VERSION)?;
+        if &key != expected {
+            return Err(format!("{}: built from different data or settings", cache_file).into());
+        }
+        let [row_offsets, buckets, values, labels]: [Vec<u32>; 4] = arrays.try_into()
+            .map_err(|_| format!("{}: expected row offset, bucket, value and label arrays", cache_file))?;
+
+        let matrix = FeatureMatrix {
+            row_offsets,
+            buckets,
+            values: values.into_iter().map(f32::from_bits).collect(),
+            labels,
+        };
+        if matrix.row_offsets.len() != matrix.labels.len() + 1 || matrix.labels.len() != key.rows
+            || matrix.buckets.len() != matrix.values.len()
+            || matrix.row_offsets.first() != Some(&0)
+            || matrix.row_offsets.windows(2).any(|pair| pair[0] > pair[1])
+            || matrix.row_offsets.last().copied() != Some(matrix.buckets.len() as u32) {
+            return Err(format!("{}: inconsistent array lengths", cache_file).into());
+        }
+        if matrix.buckets.iter().any(|&bucket| bucket as usize >= key.dimension) {
+            return Err(format!("{}: bucket outside the hash dimension", cache_file).into());
+        }
+        Ok(matrix)
+    }
+}
@@ -1,41 +1,293 @@
 use std::collections::HashMap;
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::hash::BuildHasherDefault;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, Write};
 use std::error::Error;
 use std::time::Instant;
 use rand::seq::SliceRandom;
-use rand::rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
 use csv::Reader;
 use rand::prelude::IndexedMutRandom;
+use clap::Parser;
+
+mod augment;
+mod calibration;
+mod checkpoint;
+mod cli;
+mod experiment;
+mod external;
+mod features;
+mod metrics;
+mod model;
+mod optimizer;
+mod regularization;
+mod report;
+mod schedule;
+#[cfg(test)]
+mod testing;
+mod window;
+
+use augment::{augment_sentence, augmentation_enabled};
+use calibration::Calibration;
+use checkpoint::{CheckpointOptions, TrainingState};
+use cli::{Cli, Command, EvalSplit};
+use experiment::Experiment;
+use external::{load_label_map, load_labeled_file, map_labels, DataFormat, LabeledFileOptions};
+use features::{FeatureMatrix, UNKNOWN_LABEL};
+use metrics::StoppingMetric;
+use optimizer::{Optimizer, OptimizerKind};
+use report::{ClassificationReport, ConfusionMatrix, RankingReport};
+use regularization::{LazyPenalty, Penalty, RegularizationMode};
+use schedule::{learning_rate_at, LrSchedule, PlateauState};
+use window::{windowed_examples, WindowMode};
 
 // Configuration for training
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct TrainingConfig {
-    pub learning_rate: f32,
+    pub learning_rate: f32, // Base rate; the schedule scales it per epoch
+    pub lr_schedule: LrSchedule,
+    pub lr_decay_factor: f32, // Step, exponential and plateau schedules
+    pub lr_step_epochs: usize, // Step schedule only
+    pub lr_plateau_patience: usize, // Plateau schedule only
+    pub lr_min: f32, // Floor for cosine and plateau schedules
+    pub warmup_epochs: usize, // Linear warmup before the schedule takes over
+    pub optimizer: OptimizerKind,
+    pub momentum: f32, // Momentum optimizer only
+    pub adam_beta1: f32,
+    pub adam_beta2: f32,
+    pub epsilon: f32, // AdaGrad and Adam denominator guard
     pub epochs: usize,
-    pub regularization: f32,
+    pub regularization: f32, // Penalty strength
+    pub penalty: Penalty,
+    pub l1_ratio: f32, // Share of the penalty applied as L1 for elastic net
+    pub regularization_mode: RegularizationMode,
     pub dimension: usize,
-    pub train_test_split: f32, // 0.8 means 80% training, 20% testing
+    pub train_test_split: f32, // 0.8 means 80% training (incl. validation), 20% testing, per language
     pub batch_size: usize,
+    pub update_mode: UpdateMode,
+    pub num_threads: usize, // Threads for mini-batch gradients; 0 uses every core
     pub early_stopping_patience: usize,
+    pub early_stopping_metric: StoppingMetric, // Validation metric watched by early stopping
+    pub validation_split: f32, // Fraction of each language's training sentences held out for validation
+    pub balance_strategy: BalanceStrategy,
     pub samples_per_language: usize, // New: equal samples per language
+    // Probability that an upsampled copy is cropped, recased, has punctuation stripped or
+    // inserted, gets a number injected, or gets character-level typos
+    pub augment_crop: f32,
+    pub augment_case: f32,
+    pub augment_punctuation: f32,
+    pub augment_digits: f32,
+    pub augment_noise: f32,
+    pub window_mode: WindowMode, // Short-text training: cut random word or character windows each epoch
+    pub window_min: usize,
+    pub window_max: usize,
+    pub window_probability: f32, // Share of training examples replaced by a window each epoch
+    pub calibration: Calibration, // Temperature scaling fitted on the validation split after training
+    pub class_weight_power: f32, // Class weights are (language frequency)^-power
+    pub objective: Objective,
+    pub dro_step_size: f32, // Group DRO: how sharply language weights favor high estimated losses
+    pub seed: u64, // Drives every random choice: init, balancing, split and shuffles
 }
 
 impl Default for TrainingConfig {
     fn default() -> Self {
         Self {
             learning_rate: 0.01,
-            epochs: 100,
+            lr_schedule: LrSchedule::Constant,
+            lr_decay_factor: 0.5,
+            lr_step_epochs: 30,
+            lr_plateau_patience: 5,
+            lr_min: 0.0,
+            warmup_epochs: 0,
+            optimizer: OptimizerKind::Sgd,
+            momentum: 0.9,
+            adam_beta1: 0.9,
+            adam_beta2: 0.999,
+            epsilon: 1e-8,
+            epochs: 200,
             regularization: 0.001,
+            penalty: Penalty::L2,
+            l1_ratio: 0.5,
+            regularization_mode: RegularizationMode::Lazy,
             dimension: 4096,
             train_test_split: 0.8,
-            batch_size: 32,
-            early_stopping_patience: 10,
+            batch_size: 64,
+            update_mode: UpdateMode::PerExample,
+            num_threads: 0,
+            early_stopping_patience: 20,
+            early_stopping_metric: StoppingMetric::Loss,
+            validation_split: 0.1,
+            balance_strategy: BalanceStrategy::Resample,
             samples_per_language: 1000, // Default to 1000 samples per language
+            augment_crop: 0.3,
+            augment_case: 0.2,
+            augment_punctuation: 0.2,
+            augment_digits: 0.1,
+            augment_noise: 0.1,
+            window_mode: WindowMode::Sentence,
+            window_min: 1,
+            window_max: 5,
+            window_probability: 0.5,
+            calibration: Calibration::None,
+            class_weight_power: 1.0,
+            objective: Objective::Average,
+            dro_step_size: 1.0,
+            seed: 42,
         }
     }
 }
 
+// Independent random streams derived from the seed, so that e.g. changing the
+// number of epochs does not change the initialization or the train/test split
+#[derive(Debug, Clone, Copy)]
+pub enum RngStream {
+    Init = 0,
+    Balance = 1,
+    Split = 2,
+    Shuffle = 3,
+    Window = 4,
+    EvalWindow = 5,
+    Augment = 6,
+}
+
+impl TrainingConfig {
+    // (L1, L2) coefficients of the weight penalty
+    pub fn penalty_strengths(&self) -> (f32, f32) {
+        match self.penalty {
+            Penalty::L2 => (0.0, self.regularization),
+            Penalty::L1 => (self.regularization, 0.0),
+            Penalty::ElasticNet => (self.regularization * self.l1_ratio, self.regularization * (1.0 - self.l1_ratio)),
+        }
+    }
+
+    // Reject values that would make training panic, whether they come from flags or an experiment file
+    pub fn validate(&self) -> Result<(), String> {
+        if self.batch_size == 0 {
+            return Err("batch_size must be at least 1".to_string());
+        }
+        if self.dimension == 0 {
+            return Err("dimension must be at least 1".to_string());
+        }
+        for (name, value) in [("train_test_split", self.train_test_split), ("validation_split", self.validation_split)] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("{} must be between 0 and 1, got {}", name, value));
+            }
+        }
+        if self.window_min > self.window_max {
+            return Err(format!("window_min ({}) is larger than window_max ({})", self.window_min, self.window_max));
+        }
+        Ok(())
+    }
+
+    // Threads used for mini-batch gradients, resolving 0 to the number of cores
+    pub fn thread_count(&self) -> usize {
+        if self.num_threads == 0 { num_cpus::get() } else { self.num_threads }
+    }
+
+    pub fn rng(&self, stream: RngStream) -> ChaCha8Rng {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        rng.set_stream(stream as u64);
+        rng
+    }
+}
+
+// Sparse feature vector (bucket -> value). Uses a fixed hasher so iteration order,
+// and therefore floating point summation order, is the same on every run.
+pub type FeatureMap = HashMap<u32, f32, BuildHasherDefault<DefaultHasher>>;
+
+// Accumulated per-bucket gradients (bucket -> one value per language)
+type GradientMap = HashMap<u32, Vec<f32>, BuildHasherDefault<DefaultHasher>>;
+
+// Gradient sums over (part of) a mini-batch
+struct BatchGradient {
+    weights: GradientMap,
+    intercepts: Vec<f32>,
+    // Unweighted loss sum, so reported losses are comparable across epochs
+    loss: f32,
+    processed: usize,
+    // Unweighted loss sum and example count per language, for group DRO
+    language_losses: Vec<f32>,
+    language_counts: Vec<usize>,
+}
+
+impl BatchGradient {
+    fn new(num_languages: usize) -> Self {
+        Self {
+            weights: GradientMap::default(),
+            intercepts: vec![0.0; num_languages],
+            loss: 0.0,
+            processed: 0,
+            language_losses: vec![0.0; num_languages],
+            language_counts: vec![0; num_languages],
+        }
+    }
+
+    fn merge(&mut self, other: BatchGradient) {
+        for (bucket, grads) in other.weights {
+            match self.weights.entry(bucket) {
+                Entry::Occupied(mut entry) => {
+                    for (acc, grad) in entry.get_mut().iter_mut().zip(grads) {
+                        *acc += grad;
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(grads);
+                }
+            }
+        }
+        for (acc, grad) in self.intercepts.iter_mut().zip(other.intercepts) {
+            *acc += grad;
+        }
+        self.loss += other.loss;
+        self.processed += other.processed;
+        for (acc, loss) in self.language_losses.iter_mut().zip(other.language_losses) {
+            *acc += loss;
+        }
+        for (acc, count) in self.language_counts.iter_mut().zip(other.language_counts) {
+            *acc += count;
+        }
+    }
+}
+
+// How train_step applies gradients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateMode {
+    // Plain SGD: update after every example; batch_size only groups the reported loss
+    PerExample,
+    // Average the gradients of each batch and apply one update per batch;
+    // gradients are computed in parallel on num_threads threads
+    MiniBatch,
+}
+
+// How train() counters the language imbalance of the training partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceStrategy {
+    // Down- and upsample every language to samples_per_language sentences
+    Resample,
+    // Train on every sentence once and weight each language's loss by class_weight_power
+    ClassWeight,
+}
+
+// Loss minimized by train_step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Objective {
+    // Average cross-entropy over training examples
+    Average,
+    // Group DRO: keep a running estimate of each language's loss, weight each language's loss by
+    // softmax(dro_step_size * estimates), so the worst languages dominate however rare they are
+    GroupDro,
+}
+
+// Share of a new observation in a group DRO language loss estimate
+const GROUP_LOSS_SMOOTHING: f32 = 0.1;
+
 // Training example
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct TrainingExample {
@@ -52,9 +304,9 @@ pub enum Feature {
     UnicodeClass(char),
 }
 
-const SEED: u32 = 3_242_157_231u32;
-const BIGRAM_MASK: u32 = (1 << 16) - 1;
-const TRIGRAM_MASK: u32 = (1 << 24) - 1;
+pub const SEED: u32 = 3_242_157_231u32;
+pub const BIGRAM_MASK: u32 = (1 << 16) - 1;
+pub const TRIGRAM_MASK: u32 = (1 << 24) - 1;
 
 // Japanese/CJK Unicode ranges
 const JP_PUNCT_START: u32 = 0x3000;
@@ -160,6 +412,22 @@ fn format_duration(seconds: f64) -> String {
     }
 }
 
+// Disjoint partitions of the unique sentences
+#[derive(Debug, Default)]
+pub struct DatasetSplit {
+    pub train: Vec<TrainingExample>,
+    pub validation: Vec<TrainingExample>,
+    pub test: Vec<TrainingExample>,
+}
+
+// Run options for train() that do not affect the trained model
+#[derive(Debug, Clone, Default)]
+pub struct TrainOptions {
+    pub checkpoint: Option<CheckpointOptions>,
+    // Directory for cached feature matrices, reused across runs with identical inputs
+    pub feature_cache_dir: Option<String>,
+}
+
 // Main trainer struct
 pub struct LanguageDetectorTrainer {
     pub language_codes: Vec<String>,
@@ -167,6 +435,18 @@ pub struct LanguageDetectorTrainer {
     pub weights: Vec<f32>,
     pub intercepts: Vec<f32>,
     pub config: TrainingConfig,
+    pub optimizer: Optimizer,
+    pub lazy_penalty: LazyPenalty,
+    // Loss weight per language, all 1.0 unless training with class weights
+    pub class_weights: Vec<f32>,
+    // Group DRO language weights, scaled to sum to the number of languages (all 1.0 otherwise)
+    pub group_weights: Vec<f32>,
+    // Group DRO running mean loss per language, None until the language is first seen
+    pub group_losses: Vec<Option<f32>>,
+    // Softmax temperature per language from calibration (all 1.0 when uncalibrated)
+    pub temperatures: Vec<f32>,
+    // Fingerprint of the data train() split, so eval can tell whether it is given the same file
+    pub data_fingerprint: Option<u64>,
 }
 
 impl LanguageDetectorTrainer {
@@ -175,21 +455,30 @@ impl LanguageDetectorTrainer {
         let total_weights = config.dimension * num_languages;
         
         // Initialize weights with small random values
-        let mut rng = rng();
+        let mut rng = config.rng(RngStream::Init);
         let weights: Vec<f32> = (0..total_weights)
-            .map(|_| (rand::random::<f32>() - 0.5) * 0.01)
+            .map(|_| (rng.random::<f32>() - 0.5) * 0.01)
             .collect();
         
         let intercepts: Vec<f32> = (0..num_languages)
-            .map(|_| (rand::random::<f32>() - 0.5) * 0.01)
+            .map(|_| (rng.random::<f32>() - 0.5) * 0.01)
             .collect();
 
+        let optimizer = Optimizer::new(&config, total_weights + num_languages);
+        let lazy_penalty = LazyPenalty::new(config.dimension);
         Self {
             language_codes,
             language_names,
             weights,
             intercepts,
             config,
+            optimizer,
+            lazy_penalty,
+            class_weights: vec![1.0; num_languages],
+            group_weights: vec![1.0; num_languages],
+            group_losses: vec![None; num_languages],
+            temperatures: vec![1.0; num_languages],
+            data_fingerprint: None,
         }
     }
 
@@ -205,15 +494,55 @@ impl LanguageDetectorTrainer {
         Ok(examples)
     }
 
+    // Split each language's unique sentences into train, validation and test partitions.
+    // Done before balancing so upsampled copies can never land on more than one side.
+    pub fn split_dataset(&self, data: &[TrainingExample]) -> DatasetSplit {
+        let mut rng = self.config.rng(RngStream::Split);
+        let mut lang_data: HashMap<&str, Vec<&TrainingExample>> = HashMap::new();
+        let mut seen = std::collections::HashSet::new();
+        let mut duplicates = 0;
+
+        // Group unique sentences by language
+        for example in data {
+            if seen.insert((example.lan_code.as_str(), example.sentence.as_str())) {
+                lang_data.entry(example.lan_code.as_str()).or_default().push(example);
+            } else {
+                duplicates += 1;
+            }
+        }
+
+        let mut split = DatasetSplit::default();
+        for lang_code in &self.language_codes {
+            if let Some(examples) = lang_data.get_mut(lang_code.as_str()) {
+                examples.shuffle(&mut rng);
+                // Keep at least one sentence for training
+                let test_idx = ((examples.len() as f32 * self.config.train_test_split).round() as usize)
+                    .clamp(1, examples.len());
+                let train_idx = (test_idx - (test_idx as f32 * self.config.validation_split).round() as usize)
+                    .max(1);
+                split.train.extend(examples[..train_idx].iter().map(|&ex| ex.clone()));
+                split.validation.extend(examples[train_idx..test_idx].iter().map(|&ex| ex.clone()));
+                split.test.extend(examples[test_idx..].iter().map(|&ex| ex.clone()));
+            }
+        }
+
+        println!("\nSplit {} unique sentences per language into {} train, {} validation and {} test ({} duplicates dropped)",
+                split.train.len() + split.validation.len() + split.test.len(),
+                split.train.len(), split.validation.len(), split.test.len(), duplicates);
+        split
+    }
+
     // NEW: Create balanced dataset with equal samples per language
     pub fn create_balanced_dataset(&self, data: &[TrainingExample]) -> Vec<TrainingExample> {
-        let mut rng = rng();
+        let mut rng = self.config.rng(RngStream::Balance);
+        // Augmentation draws from its own stream so its settings never change which sentences are sampled
+        let mut augment_rng = self.config.rng(RngStream::Augment);
         let mut lang_data: HashMap<String, Vec<TrainingExample>> = HashMap::new();
         
         // Group data by language
         for example in data {
             lang_data.entry(example.lan_code.clone())
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(example.clone());
         }
 
@@ -221,6 +550,7 @@ impl LanguageDetectorTrainer {
         let mut total_samples = 0;
         let mut upsampled_languages = Vec::new();
         let mut downsampled_languages = Vec::new();
+        let augment = augmentation_enabled(&self.config);
 
         println!("\nCreating balanced dataset with {} samples per language:", self.config.samples_per_language);
         
@@ -241,16 +571,24 @@ impl LanguageDetectorTrainer {
                     // First add all original examples
                     upsampled.extend_from_slice(examples);
                     
-                    // Then duplicate randomly to reach target
+                    // Then duplicate randomly to reach target, augmenting each copy
+                    let mut augmented = 0;
                     while upsampled.len() < needed {
-                        let random_example = examples.choose_mut(&mut rng).unwrap().clone();
+                        let mut random_example = examples.choose_mut(&mut rng).unwrap().clone();
+                        if augment {
+                            let variation = augment_sentence(&random_example.sentence, &self.config, &mut augment_rng);
+                            if variation != random_example.sentence {
+                                augmented += 1;
+                            }
+                            random_example.sentence = variation;
+                        }
                         upsampled.push(random_example);
                     }
                     
                     // Ensure exact count
                     upsampled.truncate(needed);
                     *examples = upsampled;
-                    upsampled_languages.push((lang_code.clone(), original_count));
+                    upsampled_languages.push((lang_code.clone(), original_count, augmented));
                 }
                 
                 balanced_data.extend_from_slice(examples);
@@ -269,10 +607,10 @@ impl LanguageDetectorTrainer {
         println!("  Downsampled languages: {}", downsampled_languages.len());
         
         if !upsampled_languages.is_empty() {
-            println!("\nUpsampled languages (original -> target):");
-            for (lang, original) in &upsampled_languages {
+            println!("\nUpsampled languages (original -> target, augmented copies):");
+            for (lang, original, augmented) in &upsampled_languages {
                 let lang_name = self.language_names.get(lang).unwrap_or(lang);
-                println!("  {}: {} -> {} ({})", lang, original, self.config.samples_per_language, lang_name);
+                println!("  {}: {} -> {}, {} augmented ({})", lang, original, self.config.samples_per_language, augmented, lang_name);
             }
         }
         
@@ -289,6 +627,35 @@ impl LanguageDetectorTrainer {
         balanced_data
     }
 
+    // Inverse-frequency loss weights (raised to class_weight_power), scaled so that the
+    // weighted example count equals the number of training sentences
+    pub fn compute_class_weights(&self, data: &[TrainingExample]) -> Vec<f32> {
+        let mut counts = vec![0usize; self.language_codes.len()];
+        for example in data {
+            if let Some(idx) = self.language_codes.iter().position(|code| code == &example.lan_code) {
+                counts[idx] += 1;
+            }
+        }
+
+        let mut weights: Vec<f32> = counts.iter()
+            .map(|&count| if count > 0 { (count as f32).powf(-self.config.class_weight_power) } else { 0.0 })
+            .collect();
+        let total = counts.iter().sum::<usize>() as f32;
+        let weighted_total: f32 = counts.iter().zip(&weights).map(|(&count, &weight)| count as f32 * weight).sum();
+        if weighted_total > 0.0 {
+            for weight in &mut weights {
+                *weight *= total / weighted_total;
+            }
+        }
+
+        println!("\nClass weights on {} training sentences (power {}):", data.len(), self.config.class_weight_power);
+        for ((lang_code, &count), &weight) in self.language_codes.iter().zip(&counts).zip(&weights) {
+            let lang_name = self.language_names.get(lang_code).unwrap_or(lang_code);
+            println!("  {}: {} samples, weight {:.4} ({})", lang_code, count, weight, lang_name);
+        }
+        weights
+    }
+
     // Helper function to convert language code to C++ enum name
     fn lang_code_to_cpp_enum(code: &str) -> String {
         // Convert language code to enum variant (capitalize first letter)
@@ -297,8 +664,8 @@ impl LanguageDetectorTrainer {
             .map(|(i, c)| if i == 0 { c.to_uppercase().collect::<String>() } else { c.to_string() })
             .collect::<String>()
     }
-    pub fn extract_features(&self, text: &str) -> HashMap<u32, f32> {
-        let mut feature_counts = HashMap::new();
+    pub fn extract_features(&self, text: &str) -> FeatureMap {
+        let mut feature_counts = FeatureMap::default();
         let mut total_features = 0u32;
         
         emit_tokens(text, |feature| {
@@ -320,7 +687,7 @@ impl LanguageDetectorTrainer {
     }
 
     // Predict language scores
-    pub fn predict(&self, features: &HashMap<u32, f32>) -> Vec<f32> {
+    pub fn predict(&self, features: &FeatureMap) -> Vec<f32> {
         let mut scores = self.intercepts.clone();
         
         for (&bucket, &count) in features {
@@ -335,6 +702,21 @@ impl LanguageDetectorTrainer {
         scores
     }
 
+    // Predict language scores for one row of a feature matrix
+    pub fn predict_row(&self, buckets: &[u32], values: &[f32]) -> Vec<f32> {
+        let num_languages = self.language_codes.len();
+        let mut scores = self.intercepts.clone();
+
+        for (&bucket, &count) in buckets.iter().zip(values) {
+            let weights = &self.weights[bucket as usize * num_languages..][..num_languages];
+            for (score, &weight) in scores.iter_mut().zip(weights) {
+                *score += weight * count;
+            }
+        }
+
+        scores
+    }
+
     // Softmax function
     pub fn softmax(scores: &[f32]) -> Vec<f32> {
         let max_score = scores.iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
@@ -347,56 +729,150 @@ impl LanguageDetectorTrainer {
         }
     }
 
-    // Training step
-    pub fn train_step(&mut self, examples: &[TrainingExample]) -> f32 {
+    // Weight of a language's loss in the training objective
+    #[inline]
+    fn loss_weight(&self, lang_idx: usize) -> f32 {
+        self.class_weights[lang_idx] * self.group_weights[lang_idx]
+    }
+
+    // Output gradient (softmax probabilities minus the one-hot target, scaled by the target's
+    // loss weight), unweighted cross-entropy loss and target language for one row
+    fn example_gradient(&self, features: &FeatureMatrix, row: usize) -> Option<(Vec<f32>, f32, usize)> {
+        let target_idx = features.labels[row];
+        let (buckets, values) = features.row(row);
+        if target_idx == UNKNOWN_LABEL || buckets.is_empty() {
+            return None;
+        }
+        let target_idx = target_idx as usize;
+
+        let scores = self.predict_row(buckets, values);
+        let mut gradient = Self::softmax(&scores);
+        
+        // Cross-entropy loss
+        let loss = -gradient[target_idx].max(1e-10).ln();
+        gradient[target_idx] -= 1.0;
+        let weight = self.loss_weight(target_idx);
+        if weight != 1.0 {
+            for grad in &mut gradient {
+                *grad *= weight;
+            }
+        }
+        Some((gradient, loss, target_idx))
+    }
+
+    // Group DRO step: fold the mean (unweighted) loss of each language seen in the last update
+    // into its running estimate, then reweight. Each estimate moves by a fixed fraction per
+    // observation, so how often a language appears does not change its weight, only its loss does.
+    fn update_group_weights(&mut self, mean_losses: impl IntoIterator<Item = (usize, f32)>) {
+        for (lang_idx, loss) in mean_losses {
+            let estimate = &mut self.group_losses[lang_idx];
+            *estimate = Some(match *estimate {
+                Some(previous) => previous + GROUP_LOSS_SMOOTHING * (loss - previous),
+                None => loss,
+            });
+        }
+        self.refresh_group_weights();
+    }
+
+    // Group DRO weights from the loss estimates: softmax(dro_step_size * estimate), scaled to sum
+    // to the number of languages. Languages not seen yet count as zero loss.
+    fn refresh_group_weights(&mut self) {
+        let scores: Vec<f32> = self.group_losses.iter()
+            .map(|estimate| self.config.dro_step_size * estimate.unwrap_or(0.0))
+            .collect();
+        let num_languages = scores.len() as f32;
+        self.group_weights = Self::softmax(&scores).into_iter().map(|p| p * num_languages).collect();
+    }
+
+    // Start a weight update for the penalty bookkeeping
+    fn begin_penalty_step(&mut self) {
+        self.lazy_penalty.begin_step();
+    }
+
+    // Apply the penalty the buckets missed since they were last updated, before predicting with them
+    fn catch_up_buckets(&mut self, buckets: impl IntoIterator<Item = u32>, learning_rate: f32) {
+        if self.config.regularization_mode == RegularizationMode::Lazy {
+            let (l1, l2) = self.config.penalty_strengths();
+            let num_languages = self.language_codes.len();
+            for bucket in buckets {
+                let bucket = bucket as usize;
+                let weights = &mut self.weights[bucket * num_languages..(bucket + 1) * num_languages];
+                self.lazy_penalty.catch_up(bucket, weights, l1, l2, learning_rate);
+            }
+        }
+    }
+
+    // Penalize a bucket after its gradient step: L2 decay, then the L1 soft-threshold
+    fn penalize_bucket(&mut self, bucket: u32, learning_rate: f32) {
+        let (l1, l2) = self.config.penalty_strengths();
+        let num_languages = self.language_codes.len();
+        let bucket = bucket as usize;
+        let weights = &mut self.weights[bucket * num_languages..(bucket + 1) * num_languages];
+        self.lazy_penalty.penalize(bucket, weights, l1, l2, learning_rate);
+    }
+
+    // Settle all pending lazy penalty steps; call before the learning rate changes or weights are read
+    fn flush_penalty(&mut self, learning_rate: f32) {
+        if self.config.regularization_mode == RegularizationMode::Lazy {
+            let (l1, l2) = self.config.penalty_strengths();
+            let num_languages = self.language_codes.len();
+            self.lazy_penalty.flush(&mut self.weights, num_languages, l1, l2, learning_rate);
+        }
+    }
+
+    // Number of exactly-zero weights, and of buckets whose weights are all zero
+    pub fn sparsity(&self) -> (usize, usize) {
+        let zero_weights = self.weights.iter().filter(|&&w| w == 0.0).count();
+        let zero_buckets = self.weights.chunks(self.language_codes.len().max(1))
+            .filter(|bucket| bucket.iter().all(|&w| w == 0.0))
+            .count();
+        (zero_weights, zero_buckets)
+    }
+
+    // Training step on the given rows of a feature matrix. Returns the mean unweighted loss; class
+    // and group DRO weights only scale the gradient, since they change as training goes on.
+    pub fn train_step(&mut self, features: &FeatureMatrix, rows: &[usize], learning_rate: f32) -> f32 {
+        match self.config.update_mode {
+            UpdateMode::PerExample => self.train_step_per_example(features, rows, learning_rate),
+            UpdateMode::MiniBatch => self.train_step_mini_batch(features, rows, learning_rate),
+        }
+    }
+
+    // Plain SGD: update weights after every example
+    fn train_step_per_example(&mut self, features: &FeatureMatrix, rows: &[usize], learning_rate: f32) -> f32 {
         let mut total_loss = 0.0;
         let mut processed = 0;
+        let num_languages = self.language_codes.len();
 
-        for example in examples {
-            if let Some(target_idx) = self.language_codes.iter().position(|code| code == &example.lan_code) {
-                let features = self.extract_features(&example.sentence);
-                if features.is_empty() {
-                    continue;
-                }
+        for &row in rows {
+            let (buckets, values) = features.row(row);
+            self.catch_up_buckets(buckets.iter().copied(), learning_rate);
+            let Some((gradient, loss, target_idx)) = self.example_gradient(features, row) else {
+                continue;
+            };
+            total_loss += loss;
+            processed += 1;
+            self.optimizer.begin_step();
+            self.begin_penalty_step();
 
-                let scores = self.predict(&features);
-                let probabilities = Self::softmax(&scores);
-                
-                // Cross-entropy loss
-                let loss = -probabilities[target_idx].max(1e-10).ln();
-                total_loss += loss;
-                processed += 1;
-
-                // Gradient descent
-                for (&bucket, &feature_count) in &features {
-                    let weight_start = bucket as usize * self.language_codes.len();
-                    
-                    for (lang_idx, &prob) in probabilities.iter().enumerate() {
-                        if weight_start + lang_idx < self.weights.len() {
-                            let gradient = if lang_idx == target_idx {
-                                feature_count * (prob - 1.0)
-                            } else {
-                                feature_count * prob
-                            };
-                            
-                            // Update with L2 regularization
-                            let weight_idx = weight_start + lang_idx;
-                            let reg_term = self.config.regularization * self.weights[weight_idx];
-                            self.weights[weight_idx] -= self.config.learning_rate * (gradient + reg_term);
-                        }
-                    }
+            // Gradient descent, then the weight penalty
+            for (&bucket, &feature_count) in buckets.iter().zip(values) {
+                let weight_start = bucket as usize * num_languages;
+                for (lang_idx, &grad) in gradient.iter().enumerate() {
+                    let weight_idx = weight_start + lang_idx;
+                    self.weights[weight_idx] -= self.optimizer.delta(weight_idx, feature_count * grad, learning_rate);
                 }
+                self.penalize_bucket(bucket, learning_rate);
+            }
 
-                // Update intercepts
-                for (lang_idx, &prob) in probabilities.iter().enumerate() {
-                    let gradient = if lang_idx == target_idx {
-                        prob - 1.0
-                    } else {
-                        prob
-                    };
-                    
-                    self.intercepts[lang_idx] -= self.config.learning_rate * gradient;
-                }
+            // Update intercepts
+            let intercept_offset = self.weights.len();
+            for (lang_idx, (intercept, &grad)) in self.intercepts.iter_mut().zip(&gradient).enumerate() {
+                *intercept -= self.optimizer.delta(intercept_offset + lang_idx, grad, learning_rate);
+            }
+
+            if self.config.objective == Objective::GroupDro {
+                self.update_group_weights([(target_idx, loss)]);
             }
         }
 
@@ -407,105 +883,289 @@ impl LanguageDetectorTrainer {
         }
     }
 
-    // Full training loop
-    pub fn train(&mut self, training_data: &[TrainingExample]) {
-        let mut rng = rng();
-        let mut best_loss = f32::INFINITY;
-        let mut patience_counter = 0;
+    // Sum of example gradients and losses over a slice of a batch
+    fn batch_gradient(&self, features: &FeatureMatrix, rows: &[usize]) -> BatchGradient {
+        let num_languages = self.language_codes.len();
+        let mut batch = BatchGradient::new(num_languages);
 
-        // Create balanced dataset first
-        let balanced_data = self.create_balanced_dataset(training_data);
-        
-        // Split balanced data
-        let mut shuffled_data = balanced_data;
-        shuffled_data.shuffle(&mut rng);
-        
-        let split_idx = (shuffled_data.len() as f32 * self.config.train_test_split) as usize;
-        let (train_data, test_data) = shuffled_data.split_at(split_idx);
+        for &row in rows {
+            let Some((gradient, loss, target_idx)) = self.example_gradient(features, row) else {
+                continue;
+            };
+            batch.loss += loss;
+            batch.processed += 1;
+            batch.language_losses[target_idx] += loss;
+            batch.language_counts[target_idx] += 1;
+
+            let (buckets, values) = features.row(row);
+            for (&bucket, &feature_count) in buckets.iter().zip(values) {
+                let accumulated = batch.weights.entry(bucket).or_insert_with(|| vec![0.0; num_languages]);
+                for (acc, &grad) in accumulated.iter_mut().zip(&gradient) {
+                    *acc += feature_count * grad;
+                }
+            }
+            for (acc, &grad) in batch.intercepts.iter_mut().zip(&gradient) {
+                *acc += grad;
+            }
+        }
+        batch
+    }
+
+    // Mini-batch gradient descent: average sparse gradients over the batch, then update once.
+    // The batch is cut into one contiguous chunk per thread and the partial sums are added in
+    // chunk order, so results depend only on the thread count; with one thread they match a
+    // plain sequential pass bit for bit.
+    fn train_step_mini_batch(&mut self, features: &FeatureMatrix, rows: &[usize], learning_rate: f32) -> f32 {
+        let num_languages = self.language_codes.len();
+        self.catch_up_buckets(rows.iter().flat_map(|&row| features.row(row).0.iter().copied()), learning_rate);
+        let chunk_size = rows.len().div_ceil(rayon::current_num_threads()).max(1);
+        let partials: Vec<BatchGradient> = rows.par_chunks(chunk_size)
+            .map(|chunk| self.batch_gradient(features, chunk))
+            .collect();
+        let mut batch = BatchGradient::new(num_languages);
+        for partial in partials {
+            batch.merge(partial);
+        }
+        if batch.processed == 0 {
+            return 0.0;
+        }
+
+        // Apply the averaged gradient, then the weight penalty, to the touched buckets
+        let scale = 1.0 / batch.processed as f32;
+        self.optimizer.begin_step();
+        self.begin_penalty_step();
+        for (&bucket, accumulated) in &batch.weights {
+            let weight_start = bucket as usize * num_languages;
+            for (lang_idx, &grad) in accumulated.iter().enumerate() {
+                let weight_idx = weight_start + lang_idx;
+                self.weights[weight_idx] -= self.optimizer.delta(weight_idx, grad * scale, learning_rate);
+            }
+            self.penalize_bucket(bucket, learning_rate);
+        }
+        let intercept_offset = self.weights.len();
+        for (lang_idx, (intercept, &grad)) in self.intercepts.iter_mut().zip(&batch.intercepts).enumerate() {
+            *intercept -= self.optimizer.delta(intercept_offset + lang_idx, grad * scale, learning_rate);
+        }
+        if self.config.objective == Objective::GroupDro {
+            self.update_group_weights((0..num_languages)
+                .filter(|&idx| batch.language_counts[idx] > 0)
+                .map(|idx| (idx, batch.language_losses[idx] / batch.language_counts[idx] as f32)));
+        }
+
+        batch.loss / batch.processed as f32
+    }
+
+    // Full training loop, optionally checkpointing and resuming
+    pub fn train(&mut self, training_data: &[TrainingExample], options: &TrainOptions) -> Result<(), Box<dyn Error>> {
+        let checkpoint = options.checkpoint.as_ref();
+        // Hold out validation and test sentences per language, then balance only the training partition
+        let split = self.split_dataset(training_data);
+        self.data_fingerprint = Some(features::fingerprint(training_data));
+        let train_data = match self.config.balance_strategy {
+            BalanceStrategy::Resample => {
+                self.class_weights = vec![1.0; self.language_codes.len()];
+                self.create_balanced_dataset(&split.train)
+            }
+            BalanceStrategy::ClassWeight => {
+                self.class_weights = self.compute_class_weights(&split.train);
+                split.train.clone()
+            }
+        };
+        let validation_data = split.validation.as_slice();
+        let test_data = split.test.as_slice();
         
-        println!("\nTraining on {} examples, testing on {} examples", train_data.len(), test_data.len());
+        println!("\nTraining on {} examples, validating on {}, testing on {} examples",
+                train_data.len(), validation_data.len(), test_data.len());
+        let metric = self.config.early_stopping_metric;
+
+        // Extract features once; epochs only shuffle row indices
+        let feature_start = Instant::now();
+        let (train_features, validation_features, test_features) = match &options.feature_cache_dir {
+            Some(dir) => {
+                std::fs::create_dir_all(dir)?;
+                let path = |name: &str| std::path::Path::new(dir).join(name).to_string_lossy().into_owned();
+                (
+                    FeatureMatrix::load_or_build(self, &train_data, &path("train.features"))?,
+                    FeatureMatrix::load_or_build(self, validation_data, &path("validation.features"))?,
+                    FeatureMatrix::load_or_build(self, test_data, &path("test.features"))?,
+                )
+            }
+            None => (
+                FeatureMatrix::build(self, &train_data),
+                FeatureMatrix::build(self, validation_data),
+                FeatureMatrix::build(self, test_data),
+            ),
+        };
+        println!("Prepared features in {:.2}s", feature_start.elapsed().as_secs_f64());
+        let mut epoch_rows: Vec<usize> = (0..train_features.len()).collect();
+
+        if validation_data.is_empty() {
+            println!("Validation split is empty, early stopping falls back to training loss");
+        }
+
+        // Fresh optimizer and loop state, or the state saved in the checkpoint when resuming
+        self.optimizer = Optimizer::new(&self.config, self.weights.len() + self.intercepts.len());
+        self.lazy_penalty = LazyPenalty::new(self.config.dimension);
+        self.group_weights = vec![1.0; self.language_codes.len()];
+        self.group_losses = vec![None; self.language_codes.len()];
+        // A calibrated --init-model would otherwise score validation with stale temperatures
+        self.temperatures = vec![1.0; self.language_codes.len()];
+        let mut state = TrainingState {
+            next_epoch: 0,
+            best_score: None,
+            best_epoch: 0,
+            patience_counter: 0,
+            shuffle_rng: self.config.rng(RngStream::Shuffle),
+            window_rng: self.config.rng(RngStream::Window),
+            plateau: PlateauState::default(),
+            best_weights: self.weights.clone(),
+            best_intercepts: self.intercepts.clone(),
+        };
+        if let Some(options) = checkpoint.filter(|options| options.resume) {
+            if std::path::Path::new(&options.path).exists() {
+                state = self.load_checkpoint(&options.path)?;
+            } else {
+                println!("No checkpoint at {}, starting from scratch", options.path);
+            }
+        }
+
+        let num_threads = self.config.thread_count();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(num_threads).build()?;
+        match self.config.update_mode {
+            UpdateMode::MiniBatch => println!("Using {} threads for mini-batch gradients", num_threads),
+            UpdateMode::PerExample if num_threads > 1 => println!("Per-example updates run on a single thread"),
+            UpdateMode::PerExample => {}
+        }
 
         let start_time = Instant::now();
+        let start_epoch = state.next_epoch;
 
-        for epoch in 0..self.config.epochs {
+        for epoch in start_epoch..self.config.epochs {
             let epoch_start = Instant::now();
-            let mut epoch_data = train_data.to_vec();
-            epoch_data.shuffle(&mut rng);
+            // Restarting from the identity order keeps the permutation independent of earlier epochs
+            for (position, row) in epoch_rows.iter_mut().enumerate() {
+                *row = position;
+            }
+            epoch_rows.shuffle(&mut state.shuffle_rng);
+            let learning_rate = learning_rate_at(&self.config, epoch, &state.plateau);
+
+            // Short-text mode trains on fresh random windows every epoch
+            let windowed_features;
+            let epoch_features = if self.config.window_mode == WindowMode::Sentence {
+                &train_features
+            } else {
+                windowed_features = FeatureMatrix::build(self, &windowed_examples(&train_data, &self.config, &mut state.window_rng));
+                &windowed_features
+            };
 
             // Process in batches
             let mut total_loss = 0.0;
             let mut num_batches = 0;
             
-            for batch in epoch_data.chunks(self.config.batch_size) {
-                let loss = self.train_step(batch);
+            for batch in epoch_rows.chunks(self.config.batch_size) {
+                let loss = pool.install(|| self.train_step(epoch_features, batch, learning_rate));
                 total_loss += loss;
                 num_batches += 1;
             }
+            self.flush_penalty(learning_rate);
 
             let avg_loss = if num_batches > 0 { total_loss / num_batches as f32 } else { 0.0 };
+            let epoch_seconds = epoch_start.elapsed().as_secs_f64();
+
+            // Score the epoch on the validation split
+            let (score, score_name) = if validation_data.is_empty() {
+                (avg_loss, "Train Loss")
+            } else {
+                (metric.value(&self.held_out_metrics(&validation_features)), metric.name())
+            };
             
             // Calculate ETA
             let elapsed = start_time.elapsed().as_secs_f64();
-            let avg_time_per_epoch = elapsed / (epoch + 1) as f64;
+            let avg_time_per_epoch = elapsed / (epoch + 1 - start_epoch) as f64;
             let remaining_epochs = self.config.epochs - (epoch + 1);
             let eta_seconds = avg_time_per_epoch * remaining_epochs as f64;
             
             // Evaluate on test data every 10 epochs
             if epoch % 10 == 0 || epoch == self.config.epochs - 1 {
-                let test_accuracy = self.evaluate(test_data);
-                println!("Epoch {}: Avg Loss = {:.4}, Test Accuracy = {:.2}% | ETA: {}", 
-                        epoch + 1, avg_loss, test_accuracy * 100.0, format_duration(eta_seconds));
+                let test_accuracy = self.held_out_metrics(&test_features).accuracy_summary(&self.language_codes);
+                println!("Epoch {}: LR = {:.6}, Avg Loss = {:.4}, {} = {:.4}, Test Accuracy = {} | {:.2}s | ETA: {}", 
+                        epoch + 1, learning_rate, avg_loss, score_name, score, test_accuracy, epoch_seconds, format_duration(eta_seconds));
             } else {
-                println!("Epoch {}: Avg Loss = {:.4} | ETA: {}", 
-                        epoch + 1, avg_loss, format_duration(eta_seconds));
+                println!("Epoch {}: LR = {:.6}, Avg Loss = {:.4}, {} = {:.4} | {:.2}s | ETA: {}", 
+                        epoch + 1, learning_rate, avg_loss, score_name, score, epoch_seconds, format_duration(eta_seconds));
             }
 
-            // Early stopping
-            if avg_loss < best_loss {
-                best_loss = avg_loss;
-                patience_counter = 0;
+            // Reduce-on-plateau watches the same score as early stopping
+            if self.config.lr_schedule == LrSchedule::Plateau {
+                let score_metric = if validation_data.is_empty() { StoppingMetric::Loss } else { metric };
+                if state.plateau.observe(&self.config, score_metric, score) {
+                    println!("{} plateaued, reducing learning rate to {:.6}",
+                            score_name, learning_rate_at(&self.config, epoch + 1, &state.plateau));
+                }
+            }
+
+            // Early stopping, remembering the weights of the best epoch
+            state.next_epoch = epoch + 1;
+            let improved = match state.best_score {
+                None => true,
+                Some(best) if validation_data.is_empty() => avg_loss < best,
+                Some(best) => metric.is_improvement(score, best),
+            };
+            if improved {
+                state.best_score = Some(score);
+                state.best_epoch = epoch + 1;
+                state.best_weights.copy_from_slice(&self.weights);
+                state.best_intercepts.copy_from_slice(&self.intercepts);
+                state.patience_counter = 0;
             } else {
-                patience_counter += 1;
-                if patience_counter >= self.config.early_stopping_patience {
+                state.patience_counter += 1;
+                if state.patience_counter >= self.config.early_stopping_patience {
                     println!("Early stopping at epoch {}", epoch + 1);
                     break;
                 }
             }
+
+            if let Some(options) = checkpoint.filter(|options| options.every_epochs > 0)
+                && state.next_epoch.is_multiple_of(options.every_epochs) {
+                self.save_checkpoint(&options.path, &state)?;
+            }
         }
 
-        let total_time = start_time.elapsed().as_secs_f64();
-        println!("Training completed in {}", format_duration(total_time));
-    }
+        // Keep the best epoch's weights rather than the last ones
+        if let Some(best_score) = state.best_score {
+            self.weights.copy_from_slice(&state.best_weights);
+            self.intercepts.copy_from_slice(&state.best_intercepts);
+            println!("Restored weights from epoch {} (best {:.4}), Test Accuracy = {}",
+                    state.best_epoch, best_score, self.held_out_metrics(&test_features).accuracy_summary(&self.language_codes));
+        }
 
-    // Evaluate model
-    pub fn evaluate(&self, test_data: &[TrainingExample]) -> f32 {
-        let mut correct = 0;
-        let mut total = 0;
-
-        for example in test_data {
-            if let Some(target_idx) = self.language_codes.iter().position(|code| code == &example.lan_code) {
-                let features = self.extract_features(&example.sentence);
-                if !features.is_empty() {
-                    let scores = self.predict(&features);
-                    let predicted_idx = scores.iter()
-                        .enumerate()
-                        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-                        .map(|(idx, _)| idx)
-                        .unwrap_or(0);
-                    
-                    if predicted_idx == target_idx {
-                        correct += 1;
-                    }
-                    total += 1;
-                }
-            }
+        // Temperatures are fitted to the final weights; without calibration they are reset to 1
+        self.calibrate(&validation_features, self.config.calibration);
+        if self.config.calibration != Calibration::None {
+            println!("Calibrated Test Accuracy = {}", self.held_out_metrics(&test_features).accuracy_summary(&self.language_codes));
         }
 
-        if total > 0 {
-            correct as f32 / total as f32
-        } else {
-            0.0
+        if self.config.window_mode != WindowMode::Sentence {
+            self.print_accuracy_by_length("Test", test_data, self.config.window_mode);
+        }
+
+        if self.config.objective == Objective::GroupDro {
+            let mut ranked: Vec<usize> = (0..self.language_codes.len()).collect();
+            ranked.sort_by(|&a, &b| self.group_weights[b].total_cmp(&self.group_weights[a]));
+            let top = ranked.iter().take(5)
+                .map(|&idx| format!("{} {:.3}", self.language_codes[idx], self.group_weights[idx]))
+                .collect::<Vec<_>>();
+            println!("Highest group DRO weights: {}", top.join(", "));
         }
+
+        let (zero_weights, zero_buckets) = self.sparsity();
+        println!("Sparsity: {} of {} weights are zero ({:.2}%), {} of {} buckets are entirely zero",
+                zero_weights, self.weights.len(), zero_weights as f32 * 100.0 / self.weights.len().max(1) as f32,
+                zero_buckets, self.config.dimension);
+
+        let total_time = start_time.elapsed().as_secs_f64();
+        println!("Training completed in {}", format_duration(total_time));
+        Ok(())
     }
 
     // Export weights to C++ header file
@@ -589,6 +1249,15 @@ impl LanguageDetectorTrainer {
             }
         }
         writeln!(file, "}};")?;
+        writeln!(file)?;
+
+        // Calibrated probabilities: softmax over score[i] / TEMPERATURES[i]
+        writeln!(file, "const float TEMPERATURES[{}] = {{", self.temperatures.len())?;
+        for chunk in self.temperatures.chunks(8) {
+            let values: Vec<String> = chunk.iter().map(|t| format!("{:.6}f", t)).collect();
+            writeln!(file, "    {},", values.join(", "))?;
+        }
+        writeln!(file, "}};")?;
 
         println!("Weights exported to {}", output_file);
         Ok(())
@@ -616,13 +1285,46 @@ impl LanguageDetectorTrainer {
     }
 }
 
-// Main function to run training
+// Main function: dispatch to the requested subcommand
 fn main() -> Result<(), Box<dyn Error>> {
+    match Cli::parse().command {
+        Command::Train(args) => run_train(*args),
+        Command::Eval(args) => run_eval(args),
+        Command::Export(args) => run_export(args),
+        Command::Predict(args) => run_predict(args),
+    }
+}
+
+fn run_train(args: cli::TrainArgs) -> Result<(), Box<dyn Error>> {
+    let experiment = args.experiment.as_deref().map(Experiment::load).transpose()?;
+
+    // Resolve paths: command-line flags, then the experiment file, then the defaults
+    let data_path = args.data.clone()
+        .or_else(|| experiment.as_ref().and_then(|e| e.data.clone()))
+        .unwrap_or_else(|| "../dataset/sentences.csv".to_string());
+    let names_path = args.language_names.clone()
+        .or_else(|| experiment.as_ref().and_then(|e| e.language_names.clone()))
+        .unwrap_or_else(|| "../dataset/lan_to_language.json".to_string());
+    let model_path = args.model.clone()
+        .or_else(|| experiment.as_ref().and_then(|e| e.model.clone()))
+        .unwrap_or_else(|| "model.bin".to_string());
+    let mut export_paths = args.export.clone();
+    if export_paths.is_empty() {
+        export_paths = experiment.as_ref().map(|e| e.export.clone()).unwrap_or_default();
+    }
+    if export_paths.is_empty() {
+        export_paths.push("weights_balanced.rs".to_string());
+    }
+    let mut allowlist = args.languages.clone();
+    if allowlist.is_empty() {
+        allowlist = experiment.as_ref().map(|e| e.languages.clone()).unwrap_or_default();
+    }
+
     // Load language mappings
-    let language_names = load_language_mappings("../dataset/lan_to_language.json")?;
+    let language_names = load_language_mappings(&names_path)?;
     
     // Load training data
-    let training_data = LanguageDetectorTrainer::load_csv_data("../dataset/sentences.csv")?;
+    let mut training_data = LanguageDetectorTrainer::load_csv_data(&data_path)?;
     
     // Get unique language codes from data
     let mut language_codes: Vec<String> = training_data.iter()
@@ -631,38 +1333,198 @@ fn main() -> Result<(), Box<dyn Error>> {
         .into_iter()
         .collect();
     language_codes.sort();
+
+    // Restrict to the allowlist, rejecting codes that are not in the data
+    if !allowlist.is_empty() {
+        if let Some(missing) = allowlist.iter().find(|code| !language_codes.contains(code)) {
+            return Err(format!("language '{}' from the allowlist has no examples in {}", missing, data_path).into());
+        }
+        language_codes.retain(|code| allowlist.contains(code));
+        training_data.retain(|ex| allowlist.contains(&ex.lan_code));
+        println!("Restricted to {} allowlisted languages ({} examples)", language_codes.len(), training_data.len());
+    }
     
     println!("Found {} unique languages", language_codes.len());
 
     // Configure training with egalitarian sampling
-    let config = TrainingConfig {
-        learning_rate: 0.01,
-        epochs: 200,
-        regularization: 0.001,
-        dimension: 4096,
-        train_test_split: 0.8,
-        batch_size: 64,
-        early_stopping_patience: 20,
-        samples_per_language: 1000, // Equal samples for all languages
-    };
+    let mut config = experiment.map(|e| e.config).unwrap_or_default();
+    args.config.apply(&mut config);
+    config.validate()?;
+    println!("Training config: {:?}", config);
 
-    // Create and train model
-    let mut trainer = LanguageDetectorTrainer::new(language_codes, language_names, config);
+    // Create a fresh model, or continue from a saved one
+    let mut trainer = match &args.init_model {
+        Some(init_path) => {
+            let mut trainer = LanguageDetectorTrainer::load_model(init_path)?;
+            if trainer.language_codes != language_codes {
+                return Err(format!("{} was trained on different languages than {}", init_path, data_path).into());
+            }
+            if trainer.config.dimension != config.dimension {
+                return Err(format!("{} has dimension {}, config asks for {}",
+                    init_path, trainer.config.dimension, config.dimension).into());
+            }
+            trainer.language_names = language_names;
+            trainer.config = config;
+            trainer
+        }
+        None => LanguageDetectorTrainer::new(language_codes, language_names, config),
+    };
     trainer.print_language_stats(&training_data);
     
     println!("\nStarting egalitarian training...");
-    trainer.train(&training_data);
+    let options = TrainOptions {
+        checkpoint: args.checkpoint.clone().map(|path| CheckpointOptions {
+            path,
+            every_epochs: args.checkpoint_every,
+            resume: args.resume,
+        }),
+        feature_cache_dir: args.feature_cache.clone(),
+    };
+    trainer.train(&training_data, &options)?;
     
     // Export results
-    trainer.export_weights("weights_balanced.rs")?;
+    trainer.save_model(&model_path)?;
+    for export_path in &export_paths {
+        trainer.export_weights(export_path)?;
+    }
     
     println!("Egalitarian training completed successfully!");
     Ok(())
 }
 
+fn run_eval(args: cli::EvalArgs) -> Result<(), Box<dyn Error>> {
+    let trainer = LanguageDetectorTrainer::load_model(&args.model)?;
+    let file_options = LabeledFileOptions {
+        format: args.format.unwrap_or_else(|| DataFormat::from_path(&args.data)),
+        label_column: args.label_column.clone(),
+        text_column: args.text_column.clone(),
+        id_column: args.id_column.clone(),
+        has_header: !args.no_header,
+    };
+    let label_map = match &args.label_map {
+        Some(path) => load_label_map(path)?,
+        None => HashMap::new(),
+    };
+    let data = map_labels(load_labeled_file(&args.data, &file_options)?, &label_map, &trainer.language_codes);
+    // Only the training data has a held-out test split; any other file is scored in full
+    let split = args.split.unwrap_or_else(|| {
+        if trainer.data_fingerprint == Some(features::fingerprint(&data)) {
+            println!("Data matches the model's training data, evaluating on its test split");
+            EvalSplit::Test
+        } else {
+            println!("Data is not the model's training data, evaluating on all of it (set --split to override)");
+            EvalSplit::All
+        }
+    });
+    // The model's seed and split fractions reproduce the split it was trained with
+    let test_data = match split {
+        EvalSplit::All => data,
+        EvalSplit::Train => trainer.split_dataset(&data).train,
+        EvalSplit::Validation => trainer.split_dataset(&data).validation,
+        EvalSplit::Test => trainer.split_dataset(&data).test,
+    };
+    let evaluations = trainer.evaluate_examples(&test_data);
+    let scores = ClassificationReport::new(&trainer.language_codes, &evaluations);
+    println!("Accuracy: {:.2}%", scores.accuracy * 100.0);
+    trainer.print_length_report(&evaluations);
+
+    scores.print(&trainer.language_names);
+    if let Some(path) = &args.scores_output {
+        scores.save(path)?;
+    }
+
+    RankingReport::new(trainer.language_codes.len(), &evaluations, &args.top_k).print(&trainer.language_codes);
+
+    let confusion = ConfusionMatrix::new(trainer.language_codes.len(), &evaluations);
+    trainer.print_confused_pairs(&confusion, &test_data, &evaluations, args.top_confused, 3);
+    if let Some(path) = &args.confusion_output {
+        confusion.save_csv(path, &trainer.language_codes)?;
+    }
+    if let Some(path) = &args.errors_output {
+        trainer.save_misclassified(path, &test_data, &evaluations)?;
+    }
+
+    let window_mode = args.window_mode.unwrap_or(trainer.config.window_mode);
+    if window_mode != WindowMode::Sentence {
+        trainer.print_accuracy_by_length("Eval", &test_data, window_mode);
+    }
+    Ok(())
+}
+
+fn run_export(args: cli::ExportArgs) -> Result<(), Box<dyn Error>> {
+    let trainer = LanguageDetectorTrainer::load_model(&args.model)?;
+    trainer.export_weights(&args.output)
+}
+
+fn run_predict(args: cli::PredictArgs) -> Result<(), Box<dyn Error>> {
+    let trainer = LanguageDetectorTrainer::load_model(&args.model)?;
+    let texts = if args.text.is_empty() {
+        std::io::stdin().lock().lines().collect::<Result<Vec<_>, _>>()?
+    } else {
+        args.text
+    };
+
+    for text in &texts {
+        let features = trainer.extract_features(text);
+        // Without features the scores are just the intercepts, which say nothing about the text
+        if features.is_empty() {
+            println!("unknown\t{}", text);
+            continue;
+        }
+        let probabilities = trainer.calibrated_probabilities(&trainer.predict(&features));
+        let mut ranked: Vec<(usize, f32)> = probabilities.into_iter().enumerate().collect();
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        let candidates: Vec<String> = ranked.iter()
+            .take(args.top.max(1))
+            .map(|&(idx, prob)| {
+                let code = &trainer.language_codes[idx];
+                let name = trainer.language_names.get(code).unwrap_or(code);
+                format!("{} ({}) {:.3}", code, name, prob)
+            })
+            .collect();
+        println!("{}\t{}", candidates.join(", "), text);
+    }
+    Ok(())
+}
 // Helper function to load language mappings
 fn load_language_mappings(file_path: &str) -> Result<HashMap<String, String>, Box<dyn Error>> {
     let file_content = std::fs::read_to_string(file_path)?;
     let mappings: HashMap<String, String> = serde_json::from_str(&file_content)?;
     Ok(mappings)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{examples, quick_config, trainer};
+
+    #[test]
+    fn same_seed_gives_identical_models() {
+        let train = |seed| {
+            let mut trainer = trainer(TrainingConfig { seed, ..quick_config() });
+            trainer.train(&examples(), &TrainOptions::default()).unwrap();
+            trainer
+        };
+        let (first, second) = (train(42), train(42));
+        assert_eq!(first.weights, second.weights);
+        assert_eq!(first.intercepts, second.intercepts);
+        assert_ne!(first.weights, train(7).weights);
+    }
+
+    // A rare language with a high loss must outweigh frequent languages with low losses
+    #[test]
+    fn group_dro_favors_rare_hard_language() {
+        let mut trainer = trainer(TrainingConfig { objective: Objective::GroupDro, ..quick_config() });
+        let (deu, eng, spa) = (0, 1, 2);
+        for _ in 0..50 {
+            for _ in 0..20 {
+                trainer.update_group_weights([(deu, 0.5)]);
+                trainer.update_group_weights([(eng, 0.3)]);
+            }
+            trainer.update_group_weights([(spa, 2.0)]);
+        }
+        assert!(trainer.group_weights[spa] > trainer.group_weights[deu], "{:?}", trainer.group_weights);
+        assert!(trainer.group_weights[deu] > trainer.group_weights[eng], "{:?}", trainer.group_weights);
+    }
+}
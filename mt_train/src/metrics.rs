@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+
+use crate::features::{FeatureMatrix, UNKNOWN_LABEL};
+use crate::LanguageDetectorTrainer;
+
+// Summary metrics on a held-out split
+#[derive(Debug, Clone, Copy)]
+pub struct HeldOutMetrics {
+    pub loss: f32,
+    pub accuracy: f32,
+    pub macro_f1: f32,
+    // Lowest per-language accuracy and the language it belongs to
+    pub worst_accuracy: f32,
+    pub worst_language: Option<usize>,
+}
+
+impl HeldOutMetrics {
+    // "99.10% (worst jpn 80.00%)"
+    pub fn accuracy_summary(&self, language_codes: &[String]) -> String {
+        match self.worst_language {
+            Some(idx) => format!("{:.2}% (worst {} {:.2}%)", self.accuracy * 100.0, language_codes[idx], self.worst_accuracy * 100.0),
+            None => format!("{:.2}%", self.accuracy * 100.0),
+        }
+    }
+}
+
+// Held-out metric watched by early stopping
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum StoppingMetric {
+    Loss,
+    Accuracy,
+    MacroF1,
+    WorstAccuracy,
+}
+
+impl StoppingMetric {
+    pub fn value(&self, metrics: &HeldOutMetrics) -> f32 {
+        match self {
+            StoppingMetric::Loss => metrics.loss,
+            StoppingMetric::Accuracy => metrics.accuracy,
+            StoppingMetric::MacroF1 => metrics.macro_f1,
+            StoppingMetric::WorstAccuracy => metrics.worst_accuracy,
+        }
+    }
+
+    pub fn is_improvement(&self, value: f32, best: f32) -> bool {
+        match self {
+            StoppingMetric::Loss => value < best,
+            StoppingMetric::Accuracy | StoppingMetric::MacroF1 | StoppingMetric::WorstAccuracy => value > best,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StoppingMetric::Loss => "Val Loss",
+            StoppingMetric::Accuracy => "Val Accuracy",
+            StoppingMetric::MacroF1 => "Val Macro-F1",
+            StoppingMetric::WorstAccuracy => "Val Worst Accuracy",
+        }
+    }
+}
+
+// Macro-averaged F1 over languages that occur in the labels or the predictions
+pub fn macro_f1(true_positives: &[usize], false_positives: &[usize], false_negatives: &[usize]) -> f32 {
+    let mut f1_sum = 0.0;
+    let mut counted = 0;
+    for ((&tp, &fp), &fn_) in true_positives.iter().zip(false_positives).zip(false_negatives) {
+        if tp + fp + fn_ == 0 {
+            continue;
+        }
+        f1_sum += 2.0 * tp as f32 / (2 * tp + fp + fn_) as f32;
+        counted += 1;
+    }
+    if counted > 0 { f1_sum / counted as f32 } else { 0.0 }
+}
+
+impl LanguageDetectorTrainer {
+    // Loss, accuracy, macro-F1 and worst-language accuracy on a feature matrix (skips unknown languages and empty inputs, like evaluate)
+    pub fn held_out_metrics(&self, features: &FeatureMatrix) -> HeldOutMetrics {
+        let num_languages = self.language_codes.len();
+        let mut true_positives = vec![0; num_languages];
+        let mut false_positives = vec![0; num_languages];
+        let mut false_negatives = vec![0; num_languages];
+        let mut language_totals = vec![0; num_languages];
+        let mut total_loss = 0.0;
+        let mut correct = 0;
+        let mut total = 0;
+
+        for row in 0..features.len() {
+            let (buckets, values) = features.row(row);
+            let target_idx = features.labels[row];
+            if target_idx == UNKNOWN_LABEL || buckets.is_empty() {
+                continue;
+            }
+            let target_idx = target_idx as usize;
+
+            let probabilities = self.calibrated_probabilities(&self.predict_row(buckets, values));
+            total_loss += -probabilities[target_idx].max(1e-10).ln();
+            let predicted_idx = probabilities.iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+                .map(|(idx, _)| idx)
+                .unwrap_or(0);
+
+            if predicted_idx == target_idx {
+                true_positives[target_idx] += 1;
+                correct += 1;
+            } else {
+                false_positives[predicted_idx] += 1;
+                false_negatives[target_idx] += 1;
+            }
+            language_totals[target_idx] += 1;
+            total += 1;
+        }
+
+        if total == 0 {
+            return HeldOutMetrics { loss: 0.0, accuracy: 0.0, macro_f1: 0.0, worst_accuracy: 0.0, worst_language: None };
+        }
+        let worst = (0..num_languages)
+            .filter(|&idx| language_totals[idx] > 0)
+            .map(|idx| (idx, true_positives[idx] as f32 / language_totals[idx] as f32))
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        HeldOutMetrics {
+            loss: total_loss / total as f32,
+            accuracy: correct as f32 / total as f32,
+            macro_f1: macro_f1(&true_positives, &false_positives, &false_negatives),
+            worst_accuracy: worst.map_or(0.0, |(_, accuracy)| accuracy),
+            worst_language: worst.map(|(idx, _)| idx),
+        }
+    }
+}
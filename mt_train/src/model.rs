@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Take, Write};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::optimizer::Optimizer;
+use crate::regularization::LazyPenalty;
+use crate::{LanguageDetectorTrainer, TrainingConfig, BIGRAM_MASK, SEED, TRIGRAM_MASK};
+
+// Model and checkpoint file layout (all integers little-endian):
+//   magic | format version u32 | header length u64 | JSON header
+//   | array count u32 | then for each array: value count u64 | 4-byte values (f32 or u32)...
+const MODEL_MAGIC: &[u8; 4] = b"WLTM";
+pub const MODEL_FORMAT_VERSION: u32 = 1;
+
+// Feature hashing parameters the weights were trained with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HashParams {
+    pub seed: u32,
+    pub bigram_mask: u32,
+    pub trigram_mask: u32,
+}
+
+impl HashParams {
+    // Parameters compiled into this binary's feature extraction
+    pub fn current() -> Self {
+        Self {
+            seed: SEED,
+            bigram_mask: BIGRAM_MASK,
+            trigram_mask: TRIGRAM_MASK,
+        }
+    }
+}
+
+// Everything except the weight arrays, stored as JSON
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModelHeader {
+    language_codes: Vec<String>,
+    // Sorted so identical models produce identical files
+    language_names: BTreeMap<String, String>,
+    config: TrainingConfig,
+    hash_params: HashParams,
+    // Calibration temperatures per language; absent in models saved before calibration existed
+    #[serde(default)]
+    temperatures: Vec<f32>,
+    // Fingerprint of the data the model was split and trained on; absent in older models
+    #[serde(default)]
+    data_fingerprint: Option<u64>,
+}
+
+// 4-byte value stored little-endian in container arrays
+pub trait Word: Copy {
+    fn to_le_bytes(self) -> [u8; 4];
+    fn from_le_bytes(bytes: [u8; 4]) -> Self;
+}
+
+impl Word for f32 {
+    fn to_le_bytes(self) -> [u8; 4] {
+        f32::to_le_bytes(self)
+    }
+
+    fn from_le_bytes(bytes: [u8; 4]) -> Self {
+        f32::from_le_bytes(bytes)
+    }
+}
+
+impl Word for u32 {
+    fn to_le_bytes(self) -> [u8; 4] {
+        u32::to_le_bytes(self)
+    }
+
+    fn from_le_bytes(bytes: [u8; 4]) -> Self {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+// Array written to a container; arrays of one file may mix value types
+#[derive(Debug, Clone, Copy)]
+pub enum Words<'a> {
+    F32(&'a [f32]),
+    U32(&'a [u32]),
+}
+
+fn write_words<W: Word>(writer: &mut impl Write, values: &[W]) -> std::io::Result<()> {
+    writer.write_all(&(values.len() as u64).to_le_bytes())?;
+    for &value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+// Read `len` bytes, refusing lengths beyond the rest of the file so a corrupt length field
+// fails cleanly instead of attempting a huge allocation
+fn read_bytes(reader: &mut Take<impl Read>, len: u64) -> std::io::Result<Vec<u8>> {
+    if len > reader.limit() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "length field exceeds the file size"));
+    }
+    let mut bytes = vec![0u8; len as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_words<W: Word>(reader: &mut Take<impl Read>) -> std::io::Result<Vec<W>> {
+    let len = read_u64(reader)?;
+    let bytes = read_bytes(reader, len.saturating_mul(4))?;
+    Ok(bytes.chunks_exact(4).map(|b| W::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+}
+
+// Write a header and arrays of 4-byte values. Goes through a temporary file and a rename,
+// so an interrupted write never leaves a truncated file behind.
+pub fn write_container(output_file: &str, magic: &[u8; 4], version: u32, header: &impl Serialize, arrays: &[Words]) -> Result<(), Box<dyn Error>> {
+    let header_json = serde_json::to_vec(header)?;
+    let tmp_file = format!("{}.tmp", output_file);
+
+    let mut file = BufWriter::new(File::create(&tmp_file)?);
+    file.write_all(magic)?;
+    file.write_all(&version.to_le_bytes())?;
+    file.write_all(&(header_json.len() as u64).to_le_bytes())?;
+    file.write_all(&header_json)?;
+    file.write_all(&(arrays.len() as u32).to_le_bytes())?;
+    for array in arrays {
+        match array {
+            Words::F32(values) => write_words(&mut file, values)?,
+            Words::U32(values) => write_words(&mut file, values)?,
+        }
+    }
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&tmp_file, output_file)?;
+    Ok(())
+}
+
+// Header plus arrays in the order they were written, all read as `W`
+pub type Container<H, W = f32> = (H, Vec<Vec<W>>);
+
+// Read a file written by write_container, checking magic and version. Arrays written with a
+// different value type can be reinterpreted with from_bits/to_bits.
+pub fn read_container<H: DeserializeOwned, W: Word>(input_file: &str, magic: &[u8; 4], version: u32) -> Result<Container<H, W>, Box<dyn Error>> {
+    let file = File::open(input_file)?;
+    let file_len = file.metadata()?.len();
+    let mut file = BufReader::new(file).take(file_len);
+
+    let mut file_magic = [0u8; 4];
+    file.read_exact(&mut file_magic)?;
+    if &file_magic != magic {
+        return Err(format!("{}: expected a {} file", input_file, String::from_utf8_lossy(magic)).into());
+    }
+    let file_version = read_u32(&mut file)?;
+    if file_version != version {
+        return Err(format!("{}: unsupported format version {} (expected {})",
+            input_file, file_version, version).into());
+    }
+
+    let header_len = read_u64(&mut file)?;
+    let header_json = read_bytes(&mut file, header_len).map_err(|e| format!("{}: {}", input_file, e))?;
+    let header: H = serde_json::from_slice(&header_json)
+        .map_err(|e| format!("{}: {}", input_file, e))?;
+    let num_arrays = read_u32(&mut file)?;
+    let arrays = (0..num_arrays).map(|_| read_words(&mut file)).collect::<Result<_, _>>()
+        .map_err(|e| format!("{}: {}", input_file, e))?;
+    Ok((header, arrays))
+}
+
+impl LanguageDetectorTrainer {
+    // Save the model so it can be evaluated, exported, used for prediction or trained further
+    pub fn save_model(&self, output_file: &str) -> Result<(), Box<dyn Error>> {
+        let header = ModelHeader {
+            language_codes: self.language_codes.clone(),
+            language_names: self.language_names.clone().into_iter().collect(),
+            config: self.config.clone(),
+            hash_params: HashParams::current(),
+            temperatures: self.temperatures.clone(),
+            data_fingerprint: self.data_fingerprint,
+        };
+        write_container(output_file, MODEL_MAGIC, MODEL_FORMAT_VERSION, &header, &[Words::F32(&self.weights), Words::F32(&self.intercepts)])?;
+
+        println!("Model saved to {}", output_file);
+        Ok(())
+    }
+
+    // Load a model written by save_model
+    pub fn load_model(model_file: &str) -> Result<Self, Box<dyn Error>> {
+        let (header, arrays): Container<ModelHeader> = read_container(model_file, MODEL_MAGIC, MODEL_FORMAT_VERSION)?;
+        let [weights, intercepts]: [Vec<f32>; 2] = arrays.try_into()
+            .map_err(|_| format!("{}: expected weight and intercept arrays", model_file))?;
+
+        if header.hash_params != HashParams::current() {
+            return Err(format!("{}: model was trained with feature hash parameters {:?}, this build uses {:?}",
+                model_file, header.hash_params, HashParams::current()).into());
+        }
+        let num_languages = header.language_codes.len();
+        if weights.len() != header.config.dimension * num_languages || intercepts.len() != num_languages {
+            return Err(format!("{}: weight shape does not match {} languages x {} dimensions",
+                model_file, num_languages, header.config.dimension).into());
+        }
+
+        let temperatures = match header.temperatures.len() {
+            0 => vec![1.0; num_languages],
+            len if len == num_languages => header.temperatures,
+            _ => return Err(format!("{}: expected {} calibration temperatures", model_file, num_languages).into()),
+        };
+
+        println!("Loaded model from {} ({} languages, {} dimensions)", model_file, num_languages, header.config.dimension);
+        // Optimizer state is not part of the model; continued training starts it fresh
+        let optimizer = Optimizer::new(&header.config, weights.len() + intercepts.len());
+        let lazy_penalty = LazyPenalty::new(header.config.dimension);
+        let class_weights = vec![1.0; num_languages];
+        let group_weights = vec![1.0; num_languages];
+        let group_losses = vec![None; num_languages];
+        Ok(Self {
+            language_codes: header.language_codes,
+            language_names: header.language_names.into_iter().collect(),
+            weights,
+            intercepts,
+            config: header.config,
+            optimizer,
+            lazy_penalty,
+            class_weights,
+            group_weights,
+            group_losses,
+            temperatures,
+            data_fingerprint: header.data_fingerprint,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{examples, quick_config, temp_path, trainer};
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut original = trainer(quick_config());
+        original.temperatures = vec![0.5, 1.0, 2.0];
+        original.data_fingerprint = Some(crate::features::fingerprint(&examples()));
+        let path = temp_path("round_trip.bin");
+        original.save_model(&path).unwrap();
+        let loaded = LanguageDetectorTrainer::load_model(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.weights, original.weights);
+        assert_eq!(loaded.intercepts, original.intercepts);
+        assert_eq!(loaded.config, original.config);
+        assert_eq!(loaded.temperatures, original.temperatures);
+        assert_eq!(loaded.data_fingerprint, original.data_fingerprint);
+        assert_eq!(loaded.language_codes, original.language_codes);
+    }
+
+    #[test]
+    fn corrupt_models_are_rejected() {
+        let path = temp_path("corrupt.bin");
+        trainer(quick_config()).save_model(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let header_len = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) as usize;
+        let first_array = 16 + header_len + 4;
+
+        let mut bad_header = bytes.clone();
+        bad_header[16] = b'x';
+        let mut bad_length = bytes.clone();
+        bad_length[first_array..first_array + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let mut short_length = bytes;
+        short_length[first_array..first_array + 8].copy_from_slice(&1u64.to_le_bytes());
+
+        for corrupt in [bad_header, bad_length, short_length] {
+            std::fs::write(&path, corrupt).unwrap();
+            assert!(LanguageDetectorTrainer::load_model(&path).is_err());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
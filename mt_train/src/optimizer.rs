@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+use crate::TrainingConfig;
+
+// Optimizer used by train_step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OptimizerKind {
+    Sgd,
+    Momentum,
+    Adagrad,
+    Adam,
+}
+
+// Turns gradients into parameter steps, keeping per-parameter state.
+// Parameters are addressed in one flat space: weights first, then intercepts.
+#[derive(Debug, Clone)]
+pub enum Optimizer {
+    Sgd,
+    Momentum {
+        momentum: f32,
+        velocity: Vec<f32>,
+    },
+    Adagrad {
+        epsilon: f32,
+        sum_squares: Vec<f32>,
+    },
+    Adam {
+        beta1: f32,
+        beta2: f32,
+        epsilon: f32,
+        first_moment: Vec<f32>,
+        second_moment: Vec<f32>,
+        // Number of update steps taken, for bias correction
+        step: u64,
+        // beta1^step and beta2^step, derived from step
+        beta1_power: f32,
+        beta2_power: f32,
+    },
+}
+
+impl Optimizer {
+    pub fn new(config: &TrainingConfig, num_params: usize) -> Self {
+        match config.optimizer {
+            OptimizerKind::Sgd => Optimizer::Sgd,
+            OptimizerKind::Momentum => Optimizer::Momentum {
+                momentum: config.momentum,
+                velocity: vec![0.0; num_params],
+            },
+            OptimizerKind::Adagrad => Optimizer::Adagrad {
+                epsilon: config.epsilon,
+                sum_squares: vec![0.0; num_params],
+            },
+            OptimizerKind::Adam => Optimizer::Adam {
+                beta1: config.adam_beta1,
+                beta2: config.adam_beta2,
+                epsilon: config.epsilon,
+                first_moment: vec![0.0; num_params],
+                second_moment: vec![0.0; num_params],
+                step: 0,
+                beta1_power: 1.0,
+                beta2_power: 1.0,
+            },
+        }
+    }
+
+    // Call once before the parameter updates of each train_step update
+    pub fn begin_step(&mut self) {
+        let next_step = self.step_count() + 1;
+        self.set_step_count(next_step);
+    }
+
+    fn set_step_count(&mut self, step_count: u64) {
+        if let Optimizer::Adam { beta1, beta2, step, beta1_power, beta2_power, .. } = self {
+            let exponent = step_count.min(i32::MAX as u64) as i32;
+            *step = step_count;
+            *beta1_power = beta1.powi(exponent);
+            *beta2_power = beta2.powi(exponent);
+        }
+    }
+
+    // Amount to subtract from parameter `param` given its gradient
+    #[inline]
+    pub fn delta(&mut self, param: usize, gradient: f32, learning_rate: f32) -> f32 {
+        match self {
+            Optimizer::Sgd => learning_rate * gradient,
+            Optimizer::Momentum { momentum, velocity } => {
+                let v = &mut velocity[param];
+                *v = *momentum * *v + gradient;
+                learning_rate * *v
+            }
+            Optimizer::Adagrad { epsilon, sum_squares } => {
+                let g2 = &mut sum_squares[param];
+                *g2 += gradient * gradient;
+                learning_rate * gradient / (g2.sqrt() + *epsilon)
+            }
+            Optimizer::Adam { beta1, beta2, epsilon, first_moment, second_moment, beta1_power, beta2_power, .. } => {
+                let m = &mut first_moment[param];
+                let v = &mut second_moment[param];
+                *m = *beta1 * *m + (1.0 - *beta1) * gradient;
+                *v = *beta2 * *v + (1.0 - *beta2) * gradient * gradient;
+                let m_hat = *m / (1.0 - *beta1_power);
+                let v_hat = *v / (1.0 - *beta2_power);
+                learning_rate * m_hat / (v_hat.sqrt() + *epsilon)
+            }
+        }
+    }
+
+    // Per-parameter state arrays, in a fixed order, for checkpoints
+    pub fn state_arrays(&self) -> Vec<&[f32]> {
+        match self {
+            Optimizer::Sgd => vec![],
+            Optimizer::Momentum { velocity, .. } => vec![velocity],
+            Optimizer::Adagrad { sum_squares, .. } => vec![sum_squares],
+            Optimizer::Adam { first_moment, second_moment, .. } => vec![first_moment, second_moment],
+        }
+    }
+
+    pub fn step_count(&self) -> u64 {
+        match self {
+            Optimizer::Adam { step, .. } => *step,
+            _ => 0,
+        }
+    }
+
+    // Restore state saved with state_arrays and step_count
+    pub fn restore(&mut self, arrays: Vec<Vec<f32>>, step_count: u64) -> Result<(), String> {
+        let expected = self.state_arrays().iter().map(|array| array.len()).collect::<Vec<_>>();
+        if arrays.iter().map(Vec::len).collect::<Vec<_>>() != expected {
+            return Err("optimizer state does not match the configured optimizer".to_string());
+        }
+        let mut arrays = arrays.into_iter();
+        match self {
+            Optimizer::Sgd => {}
+            Optimizer::Momentum { velocity, .. } => *velocity = arrays.next().unwrap(),
+            Optimizer::Adagrad { sum_squares, .. } => *sum_squares = arrays.next().unwrap(),
+            Optimizer::Adam { first_moment, second_moment, .. } => {
+                *first_moment = arrays.next().unwrap();
+                *second_moment = arrays.next().unwrap();
+            }
+        }
+        self.set_step_count(step_count);
+        Ok(())
+    }
+}
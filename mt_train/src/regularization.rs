@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+
+// Weight penalty; L1 makes weights exactly zero so they can be pruned from the export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Penalty {
+    L2,
+    L1,
+    ElasticNet,
+}
+
+// Which weights the penalty reaches on each update
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum RegularizationMode {
+    // Every weight decays on every update, applied lazily when a bucket is next touched;
+    // equivalent to a dense penalty pass over all weights after each update
+    Lazy,
+    // Only buckets present in the update are penalized (older models were trained this way)
+    Touched,
+}
+
+// Bookkeeping for the weight penalty, which is decoupled from the optimizer: each update lets the
+// optimizer apply the loss gradient alone, then shrinks the updated weights by one penalty step
+// (a proximal soft-threshold for L1, so small weights become exactly zero). `last_step` records
+// the update each bucket was last brought up to date with. Buckets are caught up before an update
+// reads them, so untouched buckets never cost anything per update. The learning rate is fixed
+// within an epoch, so the decay a bucket missed over any number of skipped updates has a closed
+// form; flush() settles every bucket at the end of an epoch.
+#[derive(Debug, Clone)]
+pub struct LazyPenalty {
+    step: u64,
+    last_step: Vec<u64>,
+}
+
+impl LazyPenalty {
+    pub fn new(num_buckets: usize) -> Self {
+        Self {
+            step: 0,
+            last_step: vec![0; num_buckets],
+        }
+    }
+
+    // Call once per weight update, after catching up the buckets it reads
+    pub fn begin_step(&mut self) {
+        self.step += 1;
+    }
+
+    // Apply the penalty steps a bucket missed since it was last penalized
+    pub fn catch_up(&mut self, bucket: usize, weights: &mut [f32], l1: f32, l2: f32, learning_rate: f32) {
+        let missed = self.step - self.last_step[bucket];
+        self.last_step[bucket] = self.step;
+        apply_missed_steps(weights, missed, l1, l2, learning_rate);
+    }
+
+    // Apply the current update's penalty step to a bucket after its gradient step
+    pub fn penalize(&mut self, bucket: usize, weights: &mut [f32], l1: f32, l2: f32, learning_rate: f32) {
+        self.last_step[bucket] = self.step;
+        apply_missed_steps(weights, 1, l1, l2, learning_rate);
+    }
+
+    // Apply all pending penalty steps to every bucket and start counting from zero again.
+    // `weights` holds `num_languages` consecutive weights per bucket.
+    pub fn flush(&mut self, weights: &mut [f32], num_languages: usize, l1: f32, l2: f32, learning_rate: f32) {
+        for (bucket_weights, last_step) in weights.chunks_mut(num_languages.max(1)).zip(&mut self.last_step) {
+            apply_missed_steps(bucket_weights, self.step - *last_step, l1, l2, learning_rate);
+            *last_step = 0;
+        }
+        self.step = 0;
+    }
+}
+
+// Result of `steps` consecutive penalty-only updates w <- shrink(w * (1 - a), b) with
+// a = learning_rate * l2 and b = learning_rate * l1, where shrink soft-thresholds towards zero:
+//   |w_k| = max(0, |w| (1 - a)^k - b (1 - (1 - a)^k) / a)   (b k when a = 0)
+// The magnitude only decreases, so clamping once at the end matches clamping at every step.
+fn apply_missed_steps(weights: &mut [f32], steps: u64, l1: f32, l2: f32, learning_rate: f32) {
+    if steps == 0 {
+        return;
+    }
+    let a = (learning_rate * l2) as f64;
+    let b = (learning_rate * l1) as f64;
+    let factor = (1.0 - a).powf(steps as f64);
+    let shrink = if a > 0.0 { b * (1.0 - factor) / a } else { b * steps as f64 };
+    for weight in weights {
+        let magnitude = (weight.abs() as f64 * factor - shrink).max(0.0);
+        *weight = if magnitude == 0.0 { 0.0 } else { (magnitude as f32).copysign(*weight) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::FeatureMatrix;
+    use crate::optimizer::OptimizerKind;
+    use crate::testing::{examples, trainer};
+    use crate::{TrainingConfig, UpdateMode};
+
+    // Lazy catch-up must give the same weights as penalizing every weight after every update,
+    // whatever the optimizer, because the penalty never goes through the optimizer
+    #[test]
+    fn lazy_penalty_matches_dense_pass() {
+        let learning_rate = 0.05;
+        for optimizer in [OptimizerKind::Sgd, OptimizerKind::Momentum, OptimizerKind::Adagrad, OptimizerKind::Adam] {
+            for update_mode in [UpdateMode::PerExample, UpdateMode::MiniBatch] {
+                let config = TrainingConfig {
+                    optimizer,
+                    update_mode,
+                    penalty: Penalty::ElasticNet,
+                    regularization: 0.05,
+                    regularization_mode: RegularizationMode::Lazy,
+                    dimension: 64,
+                    ..TrainingConfig::default()
+                };
+                let (l1, l2) = config.penalty_strengths();
+                let mut lazy = trainer(config.clone());
+                let mut dense = trainer(TrainingConfig { regularization: 0.0, ..config });
+                let features = FeatureMatrix::build(&lazy, &examples());
+                let batches: Vec<Vec<usize>> = (0..40).map(|step| vec![step % 9, (step * 4 + 1) % 9]).collect();
+
+                for rows in &batches {
+                    let updates: Vec<&[usize]> = match update_mode {
+                        UpdateMode::PerExample => rows.chunks(1).collect(),
+                        UpdateMode::MiniBatch => vec![rows],
+                    };
+                    for update in updates {
+                        dense.train_step(&features, update, learning_rate);
+                        apply_missed_steps(&mut dense.weights, 1, l1, l2, learning_rate);
+                        lazy.train_step(&features, update, learning_rate);
+                    }
+                }
+                lazy.flush_penalty(learning_rate);
+
+                let max_difference = lazy.weights.iter().chain(&lazy.intercepts)
+                    .zip(dense.weights.iter().chain(&dense.intercepts))
+                    .map(|(a, b)| (a - b).abs())
+                    .fold(0.0, f32::max);
+                assert!(max_difference < 1e-5, "{:?} {:?}: parameters differ by {}", optimizer, update_mode, max_difference);
+            }
+        }
+    }
+
+    // Strong L1 must zero weights of buckets in every update, not only the ones the flush reaches
+    #[test]
+    fn strong_l1_zeroes_updated_buckets() {
+        let config = TrainingConfig {
+            update_mode: UpdateMode::MiniBatch,
+            penalty: Penalty::L1,
+            regularization: 5.0,
+            dimension: 64,
+            ..TrainingConfig::default()
+        };
+        let mut trainer = trainer(config);
+        let features = FeatureMatrix::build(&trainer, &examples());
+        let rows: Vec<usize> = (0..features.len()).collect();
+        for _ in 0..10 {
+            trainer.train_step(&features, &rows, 0.1);
+        }
+
+        let num_languages = trainer.language_codes.len();
+        let mut updated: Vec<usize> = features.buckets.iter().map(|&bucket| bucket as usize).collect();
+        updated.sort_unstable();
+        updated.dedup();
+        for bucket in updated {
+            let weights = &trainer.weights[bucket * num_languages..(bucket + 1) * num_languages];
+            assert!(weights.iter().all(|&w| w == 0.0), "bucket {} kept weights {:?}", bucket, weights);
+        }
+    }
+}
@@ -0,0 +1,512 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+
+use rayon::prelude::*;
+
+use crate::{LanguageDetectorTrainer, TrainingExample};
+
+// Upper bounds (inclusive) of the character length buckets; the last bucket is open-ended
+const CHAR_LENGTH_BUCKETS: &[usize] = &[10, 25, 50, 100, 200];
+// Upper bounds (inclusive) of the feature count buckets, after the bucket of empty inputs
+const FEATURE_COUNT_BUCKETS: &[usize] = &[5, 10, 25, 50, 100];
+
+// Prediction for one labeled example
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    // Index of the labeled language, None if the model does not know it
+    pub target: Option<usize>,
+    // Softmax probabilities, empty when the input has no features
+    pub probabilities: Vec<f32>,
+    pub predicted: Option<usize>,
+    pub char_count: usize,
+    pub feature_count: usize,
+}
+
+impl Evaluation {
+    pub fn is_empty(&self) -> bool {
+        self.probabilities.is_empty()
+    }
+
+    pub fn is_correct(&self) -> bool {
+        self.target.is_some() && self.predicted == self.target
+    }
+}
+
+// Index of the bucket `value` falls in, given inclusive upper bounds
+fn bucket_index(bounds: &[usize], value: usize) -> usize {
+    bounds.iter().position(|&bound| value <= bound).unwrap_or(bounds.len())
+}
+
+// "1-10", "11-25", ..., "201+" for bounds starting after `first`
+fn bucket_labels(bounds: &[usize], first: usize) -> Vec<String> {
+    let mut labels = Vec::new();
+    let mut low = first;
+    for &bound in bounds {
+        labels.push(format!("{}-{}", low, bound));
+        low = bound + 1;
+    }
+    labels.push(format!("{}+", low));
+    labels
+}
+
+// Correct and scored counts per bucket; empty inputs are counted separately
+#[derive(Debug, Clone)]
+struct BucketCounts {
+    correct: Vec<usize>,
+    scored: Vec<usize>,
+    empty: Vec<usize>,
+}
+
+impl BucketCounts {
+    fn new(num_buckets: usize) -> Self {
+        Self {
+            correct: vec![0; num_buckets],
+            scored: vec![0; num_buckets],
+            empty: vec![0; num_buckets],
+        }
+    }
+
+    fn add(&mut self, bucket: usize, evaluation: &Evaluation) {
+        if evaluation.is_empty() {
+            self.empty[bucket] += 1;
+        } else {
+            self.scored[bucket] += 1;
+            if evaluation.is_correct() {
+                self.correct[bucket] += 1;
+            }
+        }
+    }
+
+    fn accuracy(&self, bucket: usize) -> Option<f32> {
+        (self.scored[bucket] > 0).then(|| self.correct[bucket] as f32 / self.scored[bucket] as f32)
+    }
+}
+
+fn format_accuracy(accuracy: Option<f32>) -> String {
+    accuracy.map_or("-".to_string(), |accuracy| format!("{:.2}%", accuracy * 100.0))
+}
+
+impl LanguageDetectorTrainer {
+    // Predict every example (in parallel, keeping example order)
+    pub fn evaluate_examples(&self, examples: &[TrainingExample]) -> Vec<Evaluation> {
+        examples.par_iter()
+            .map(|example| {
+                let target = self.language_codes.iter().position(|code| code == &example.lan_code);
+                let features = self.extract_features(&example.sentence);
+                let probabilities = if features.is_empty() { Vec::new() } else { self.calibrated_probabilities(&self.predict(&features)) };
+                let predicted = probabilities.iter()
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+                    .map(|(idx, _)| idx);
+                Evaluation {
+                    target,
+                    probabilities,
+                    predicted,
+                    char_count: example.sentence.chars().count(),
+                    feature_count: features.len(),
+                }
+            })
+            .collect()
+    }
+
+    // Accuracy by character length and by feature count, overall and per language, and the
+    // share of inputs without features (which accuracy figures leave out)
+    pub fn print_length_report(&self, evaluations: &[Evaluation]) {
+        let known: Vec<&Evaluation> = evaluations.iter().filter(|e| e.target.is_some()).collect();
+        let empty = known.iter().filter(|e| e.is_empty()).count();
+        println!("\nEmpty-feature inputs: {} of {} ({:.2}%), left out of accuracy",
+                empty, known.len(), empty as f32 * 100.0 / known.len().max(1) as f32);
+        if known.len() < evaluations.len() {
+            println!("Skipped {} examples of languages the model does not know", evaluations.len() - known.len());
+        }
+
+        self.print_bucket_tables("character length", &bucket_labels(CHAR_LENGTH_BUCKETS, 0), &known,
+                |e| bucket_index(CHAR_LENGTH_BUCKETS, e.char_count));
+        let mut feature_labels = vec!["0".to_string()];
+        feature_labels.extend(bucket_labels(FEATURE_COUNT_BUCKETS, 1));
+        self.print_bucket_tables("feature count", &feature_labels, &known,
+                |e| if e.feature_count == 0 { 0 } else { 1 + bucket_index(FEATURE_COUNT_BUCKETS, e.feature_count) });
+    }
+
+    fn print_bucket_tables(&self, name: &str, labels: &[String], evaluations: &[&Evaluation], bucket_of: impl Fn(&Evaluation) -> usize) {
+        let num_languages = self.language_codes.len();
+        let mut overall = BucketCounts::new(labels.len());
+        let mut per_language = vec![BucketCounts::new(labels.len()); num_languages];
+        for evaluation in evaluations {
+            let bucket = bucket_of(evaluation);
+            overall.add(bucket, evaluation);
+            if let Some(target) = evaluation.target {
+                per_language[target].add(bucket, evaluation);
+            }
+        }
+
+        println!("\nAccuracy by {}:", name);
+        println!("    bucket   scored    empty  accuracy");
+        for (bucket, label) in labels.iter().enumerate() {
+            println!("  {:>8} {:>8} {:>8} {:>9}", label, overall.scored[bucket], overall.empty[bucket],
+                    format_accuracy(overall.accuracy(bucket)));
+        }
+
+        println!("\nAccuracy by {} per language:", name);
+        let header: String = labels.iter().map(|label| format!(" {:>8}", label)).collect();
+        println!("  {:<8}{} {:>8}", "language", header, "empty");
+        for (lang_idx, counts) in per_language.iter().enumerate() {
+            let total: usize = counts.scored.iter().chain(&counts.empty).sum();
+            if total == 0 {
+                continue;
+            }
+            let cells: String = (0..labels.len()).map(|bucket| format!(" {:>8}", format_accuracy(counts.accuracy(bucket)))).collect();
+            let empty: usize = counts.empty.iter().sum();
+            println!("  {:<8}{} {:>7.2}%", self.language_codes[lang_idx], cells, empty as f32 * 100.0 / total as f32);
+        }
+    }
+}
+
+// Precision, recall and F1 of one language (or an average), with its test support
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LanguageScores {
+    pub language: String,
+    pub precision: f32,
+    pub recall: f32,
+    pub f1: f32,
+    pub support: usize,
+}
+
+// Per-language scores with macro and support-weighted averages
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ClassificationReport {
+    pub accuracy: f32,
+    pub languages: Vec<LanguageScores>,
+    pub macro_average: LanguageScores,
+    pub weighted_average: LanguageScores,
+}
+
+impl ClassificationReport {
+    // Scores over examples of known languages that produced features. Languages that are
+    // neither labeled nor predicted are left out, like in the macro-F1 used for early stopping.
+    pub fn new(language_codes: &[String], evaluations: &[Evaluation]) -> Self {
+        let num_languages = language_codes.len();
+        let mut true_positives = vec![0usize; num_languages];
+        let mut false_positives = vec![0usize; num_languages];
+        let mut false_negatives = vec![0usize; num_languages];
+        for evaluation in evaluations {
+            let (Some(target), Some(predicted)) = (evaluation.target, evaluation.predicted) else {
+                continue;
+            };
+            if predicted == target {
+                true_positives[target] += 1;
+            } else {
+                false_positives[predicted] += 1;
+                false_negatives[target] += 1;
+            }
+        }
+
+        let ratio = |num: usize, den: usize| if den > 0 { num as f32 / den as f32 } else { 0.0 };
+        let languages: Vec<LanguageScores> = (0..num_languages)
+            .filter(|&idx| true_positives[idx] + false_positives[idx] + false_negatives[idx] > 0)
+            .map(|idx| {
+                let (tp, fp, fn_) = (true_positives[idx], false_positives[idx], false_negatives[idx]);
+                LanguageScores {
+                    language: language_codes[idx].clone(),
+                    precision: ratio(tp, tp + fp),
+                    recall: ratio(tp, tp + fn_),
+                    f1: ratio(2 * tp, 2 * tp + fp + fn_),
+                    support: tp + fn_,
+                }
+            })
+            .collect();
+
+        let support: usize = languages.iter().map(|scores| scores.support).sum();
+        let average = |name: &str, weight: &dyn Fn(&LanguageScores) -> f32| {
+            let total_weight: f32 = languages.iter().map(weight).sum();
+            let mean = |value: fn(&LanguageScores) -> f32| if total_weight > 0.0 {
+                languages.iter().map(|scores| value(scores) * weight(scores)).sum::<f32>() / total_weight
+            } else {
+                0.0
+            };
+            LanguageScores {
+                language: name.to_string(),
+                precision: mean(|scores| scores.precision),
+                recall: mean(|scores| scores.recall),
+                f1: mean(|scores| scores.f1),
+                support,
+            }
+        };
+        let macro_average = average("macro avg", &|_| 1.0);
+        let weighted_average = average("weighted avg", &|scores| scores.support as f32);
+
+        Self {
+            accuracy: ratio(true_positives.iter().sum(), support),
+            languages,
+            macro_average,
+            weighted_average,
+        }
+    }
+
+    // Table sorted by F1, worst languages first
+    pub fn print(&self, language_names: &HashMap<String, String>) {
+        let mut sorted: Vec<&LanguageScores> = self.languages.iter().collect();
+        sorted.sort_by(|a, b| a.f1.total_cmp(&b.f1).then_with(|| b.support.cmp(&a.support)));
+
+        println!("\nPer-language scores (worst F1 first):");
+        println!("  {:<12} {:>9} {:>9} {:>9} {:>8}  name", "language", "precision", "recall", "f1", "support");
+        for scores in sorted.into_iter().chain([&self.macro_average, &self.weighted_average]) {
+            let name = language_names.get(&scores.language).map_or("", String::as_str);
+            println!("  {:<12} {:>9.4} {:>9.4} {:>9.4} {:>8}  {}",
+                    scores.language, scores.precision, scores.recall, scores.f1, scores.support, name);
+        }
+    }
+
+    // Write as JSON, or as CSV rows (averages last) when the path ends in .csv
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        if path.ends_with(".csv") {
+            let mut writer = csv::Writer::from_path(path)?;
+            for scores in self.languages.iter().chain([&self.macro_average, &self.weighted_average]) {
+                writer.serialize(scores)?;
+            }
+            writer.flush()?;
+        } else {
+            std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        }
+        println!("Per-language scores written to {}", path);
+        Ok(())
+    }
+}
+
+// Counts of (labeled language, predicted language) over examples that produced features
+#[derive(Debug, Clone)]
+pub struct ConfusionMatrix {
+    // counts[target][predicted]
+    pub counts: Vec<Vec<usize>>,
+}
+
+// Two languages mixed up in either direction
+#[derive(Debug, Clone)]
+pub struct ConfusedPair {
+    pub first: usize,
+    pub second: usize,
+    // Examples of `first` predicted as `second`, and the other way round
+    pub first_as_second: usize,
+    pub second_as_first: usize,
+}
+
+impl ConfusedPair {
+    pub fn total(&self) -> usize {
+        self.first_as_second + self.second_as_first
+    }
+}
+
+impl ConfusionMatrix {
+    pub fn new(num_languages: usize, evaluations: &[Evaluation]) -> Self {
+        let mut counts = vec![vec![0; num_languages]; num_languages];
+        for evaluation in evaluations {
+            if let (Some(target), Some(predicted)) = (evaluation.target, evaluation.predicted) {
+                counts[target][predicted] += 1;
+            }
+        }
+        Self { counts }
+    }
+
+    // Rows are labeled languages, columns predicted languages
+    pub fn save_csv(&self, path: &str, language_codes: &[String]) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(std::iter::once("true\\predicted").chain(language_codes.iter().map(String::as_str)))?;
+        for (code, row) in language_codes.iter().zip(&self.counts) {
+            writer.write_record(std::iter::once(code.clone()).chain(row.iter().map(usize::to_string)))?;
+        }
+        writer.flush()?;
+        println!("Confusion matrix written to {}", path);
+        Ok(())
+    }
+
+    // Language pairs with the most errors between them, most confused first
+    pub fn most_confused_pairs(&self, top: usize) -> Vec<ConfusedPair> {
+        let num_languages = self.counts.len();
+        let mut pairs: Vec<ConfusedPair> = (0..num_languages)
+            .flat_map(|first| (first + 1..num_languages).map(move |second| (first, second)))
+            .map(|(first, second)| ConfusedPair {
+                first,
+                second,
+                first_as_second: self.counts[first][second],
+                second_as_first: self.counts[second][first],
+            })
+            .filter(|pair| pair.total() > 0)
+            .collect();
+        pairs.sort_by(|a, b| b.total().cmp(&a.total()).then_with(|| (a.first, a.second).cmp(&(b.first, b.second))));
+        pairs.truncate(top);
+        pairs
+    }
+}
+
+impl LanguageDetectorTrainer {
+    // Top confused pairs with up to `examples_per_direction` misclassified sentences each way
+    pub fn print_confused_pairs(&self, matrix: &ConfusionMatrix, examples: &[TrainingExample], evaluations: &[Evaluation],
+                                top: usize, examples_per_direction: usize) {
+        let pairs = matrix.most_confused_pairs(top);
+        if pairs.is_empty() {
+            println!("\nNo confused language pairs");
+            return;
+        }
+        println!("\nMost confused language pairs:");
+        for (rank, pair) in pairs.iter().enumerate() {
+            let (first, second) = (&self.language_codes[pair.first], &self.language_codes[pair.second]);
+            println!("  {}. {} <-> {}: {} errors ({} as {}: {}, {} as {}: {})", rank + 1, first, second, pair.total(),
+                    first, second, pair.first_as_second, second, first, pair.second_as_first);
+            for (target, predicted) in [(pair.first, pair.second), (pair.second, pair.first)] {
+                let sentences = examples.iter().zip(evaluations)
+                    .filter(|(_, e)| e.target == Some(target) && e.predicted == Some(predicted))
+                    .take(examples_per_direction);
+                for (example, _) in sentences {
+                    println!("       {} as {}: {}", self.language_codes[target], self.language_codes[predicted], example.sentence);
+                }
+            }
+        }
+    }
+}
+
+// One misclassified example for error analysis
+#[derive(Debug, Clone, serde::Serialize)]
+struct Misclassification<'a> {
+    id: u32,
+    lan_code: &'a str,
+    sentence: &'a str,
+    predicted: &'a str,
+    // Three most probable languages with their softmax probabilities
+    top: Vec<RankedLanguage<'a>>,
+    feature_count: usize,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct RankedLanguage<'a> {
+    language: &'a str,
+    probability: f32,
+}
+
+impl LanguageDetectorTrainer {
+    // Write every misclassified example (of a known language, with features) as JSON lines,
+    // or as CSV when the path ends in .csv
+    pub fn save_misclassified(&self, path: &str, examples: &[TrainingExample], evaluations: &[Evaluation]) -> Result<(), Box<dyn Error>> {
+        let rows: Vec<Misclassification> = examples.iter().zip(evaluations)
+            .filter(|(_, e)| e.target.is_some() && !e.is_empty() && !e.is_correct())
+            .map(|(example, evaluation)| {
+                let mut ranked: Vec<usize> = (0..evaluation.probabilities.len()).collect();
+                ranked.sort_by(|&a, &b| evaluation.probabilities[b].total_cmp(&evaluation.probabilities[a]));
+                Misclassification {
+                    id: example.id,
+                    lan_code: &example.lan_code,
+                    sentence: &example.sentence,
+                    predicted: evaluation.predicted.map_or("", |idx| self.language_codes[idx].as_str()),
+                    top: ranked.iter().take(3)
+                        .map(|&idx| RankedLanguage {
+                            language: &self.language_codes[idx],
+                            probability: evaluation.probabilities[idx],
+                        })
+                        .collect(),
+                    feature_count: evaluation.feature_count,
+                }
+            })
+            .collect();
+
+        if path.ends_with(".csv") {
+            let mut writer = csv::Writer::from_path(path)?;
+            writer.write_record(["id", "lan_code", "sentence", "predicted",
+                "top1", "top1_probability", "top2", "top2_probability", "top3", "top3_probability", "feature_count"])?;
+            for row in &rows {
+                let mut record = vec![row.id.to_string(), row.lan_code.to_string(), row.sentence.to_string(), row.predicted.to_string()];
+                for rank in 0..3 {
+                    match row.top.get(rank) {
+                        Some(ranked) => record.extend([ranked.language.to_string(), format!("{:.6}", ranked.probability)]),
+                        None => record.extend([String::new(), String::new()]),
+                    }
+                }
+                record.push(row.feature_count.to_string());
+                writer.write_record(&record)?;
+            }
+            writer.flush()?;
+        } else {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            for row in &rows {
+                serde_json::to_writer(&mut file, row)?;
+                writeln!(file)?;
+            }
+            file.flush()?;
+        }
+        println!("{} misclassified examples written to {}", rows.len(), path);
+        Ok(())
+    }
+}
+
+impl Evaluation {
+    // 1-based rank of the labeled language among the predictions (ties rank optimistically)
+    pub fn target_rank(&self) -> Option<usize> {
+        let target = self.target?;
+        let target_probability = *self.probabilities.get(target)?;
+        Some(1 + self.probabilities.iter().filter(|&&p| p > target_probability).count())
+    }
+}
+
+// Top-k accuracies and mean reciprocal rank of the labeled language, overall and per language
+#[derive(Debug, Clone)]
+pub struct RankingReport {
+    pub ks: Vec<usize>,
+    // Index 0 is overall, then one entry per language
+    support: Vec<usize>,
+    hits: Vec<Vec<usize>>,
+    reciprocal_rank_sums: Vec<f64>,
+}
+
+impl RankingReport {
+    pub fn new(num_languages: usize, evaluations: &[Evaluation], ks: &[usize]) -> Self {
+        let mut report = Self {
+            ks: ks.to_vec(),
+            support: vec![0; num_languages + 1],
+            hits: vec![vec![0; ks.len()]; num_languages + 1],
+            reciprocal_rank_sums: vec![0.0; num_languages + 1],
+        };
+        for evaluation in evaluations {
+            let (Some(target), Some(rank)) = (evaluation.target, evaluation.target_rank()) else {
+                continue;
+            };
+            for slot in [0, target + 1] {
+                report.support[slot] += 1;
+                report.reciprocal_rank_sums[slot] += 1.0 / rank as f64;
+                for (hits, &k) in report.hits[slot].iter_mut().zip(ks) {
+                    if rank <= k {
+                        *hits += 1;
+                    }
+                }
+            }
+        }
+        report
+    }
+
+    pub fn top_k_accuracy(&self, k_idx: usize, language: Option<usize>) -> f32 {
+        let slot = language.map_or(0, |idx| idx + 1);
+        self.hits[slot][k_idx] as f32 / self.support[slot].max(1) as f32
+    }
+
+    pub fn mean_reciprocal_rank(&self, language: Option<usize>) -> f32 {
+        let slot = language.map_or(0, |idx| idx + 1);
+        (self.reciprocal_rank_sums[slot] / self.support[slot].max(1) as f64) as f32
+    }
+
+    // Overall row first, then languages in model order
+    pub fn print(&self, language_codes: &[String]) {
+        println!("\nTop-k accuracy and mean reciprocal rank:");
+        let header: String = self.ks.iter().map(|k| format!(" {:>8}", format!("top-{}", k))).collect();
+        println!("  {:<12} {:>8}{} {:>8}", "language", "support", header, "mrr");
+        let rows = std::iter::once(None).chain((0..language_codes.len()).map(Some));
+        for language in rows {
+            let slot = language.map_or(0, |idx| idx + 1);
+            if self.support[slot] == 0 {
+                continue;
+            }
+            let cells: String = (0..self.ks.len())
+                .map(|k_idx| format!(" {:>7.2}%", self.top_k_accuracy(k_idx, language) * 100.0))
+                .collect();
+            let name = language.map_or("overall", |idx| language_codes[idx].as_str());
+            println!("  {:<12} {:>8}{} {:>8.4}", name, self.support[slot], cells, self.mean_reciprocal_rank(language));
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+
+use crate::TrainingConfig;
+use crate::metrics::StoppingMetric;
+
+// How the learning rate changes over epochs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LrSchedule {
+    Constant,
+    // Multiply by lr_decay_factor every lr_step_epochs epochs
+    Step,
+    // Multiply by lr_decay_factor every epoch
+    Exponential,
+    // Cosine annealing from learning_rate down to lr_min over all epochs
+    Cosine,
+    // Multiply by lr_decay_factor when the validation metric stalls for lr_plateau_patience epochs
+    Plateau,
+}
+
+// Reduce-on-plateau bookkeeping, saved in checkpoints
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlateauState {
+    pub scale: f32,
+    pub best: Option<f32>,
+    pub bad_epochs: usize,
+}
+
+impl Default for PlateauState {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            best: None,
+            bad_epochs: 0,
+        }
+    }
+}
+
+impl PlateauState {
+    // Record an epoch's validation score; returns true when the rate was just reduced
+    pub fn observe(&mut self, config: &TrainingConfig, metric: StoppingMetric, score: f32) -> bool {
+        if self.best.is_none_or(|best| metric.is_improvement(score, best)) {
+            self.best = Some(score);
+            self.bad_epochs = 0;
+            return false;
+        }
+        self.bad_epochs += 1;
+        if self.bad_epochs >= config.lr_plateau_patience {
+            self.scale *= config.lr_decay_factor;
+            self.bad_epochs = 0;
+            return true;
+        }
+        false
+    }
+}
+
+// Learning rate for a 0-based epoch, including linear warmup
+pub fn learning_rate_at(config: &TrainingConfig, epoch: usize, plateau: &PlateauState) -> f32 {
+    let base = config.learning_rate;
+    let scheduled = match config.lr_schedule {
+        LrSchedule::Constant => base,
+        LrSchedule::Step => {
+            let steps = epoch / config.lr_step_epochs.max(1);
+            base * config.lr_decay_factor.powi(steps as i32)
+        }
+        LrSchedule::Exponential => base * config.lr_decay_factor.powi(epoch as i32),
+        LrSchedule::Cosine => {
+            let progress = epoch as f32 / config.epochs.max(1) as f32;
+            config.lr_min + 0.5 * (base - config.lr_min) * (1.0 + (std::f32::consts::PI * progress).cos())
+        }
+        LrSchedule::Plateau => (base * plateau.scale).max(config.lr_min),
+    };
+
+    if epoch < config.warmup_epochs {
+        scheduled * (epoch + 1) as f32 / config.warmup_epochs as f32
+    } else {
+        scheduled
+    }
+}
@@ -0,0 +1,44 @@
+// Shared fixtures for unit tests
+use std::collections::HashMap;
+
+use crate::{LanguageDetectorTrainer, TrainingConfig, TrainingExample};
+
+// A few short sentences in each of three languages
+pub fn examples() -> Vec<TrainingExample> {
+    [
+        ("eng", "the quick brown fox"), ("eng", "jumps over the lazy dog"), ("eng", "hello world"),
+        ("spa", "el rápido zorro marrón"), ("spa", "salta sobre el perro"), ("spa", "hola mundo"),
+        ("deu", "der schnelle braune fuchs"), ("deu", "springt über den hund"), ("deu", "hallo welt"),
+    ]
+    .iter()
+    .enumerate()
+    .map(|(id, (lan_code, sentence))| TrainingExample {
+        id: id as u32,
+        lan_code: lan_code.to_string(),
+        sentence: sentence.to_string(),
+    })
+    .collect()
+}
+
+pub fn trainer(config: TrainingConfig) -> LanguageDetectorTrainer {
+    let codes = ["deu", "eng", "spa"].map(String::from).to_vec();
+    LanguageDetectorTrainer::new(codes, HashMap::new(), config)
+}
+
+// Small, fast config for tests that run train()
+pub fn quick_config() -> TrainingConfig {
+    TrainingConfig {
+        epochs: 4,
+        dimension: 64,
+        samples_per_language: 20,
+        ..TrainingConfig::default()
+    }
+}
+
+// Path in the temporary directory that is unique to this process and `name`
+pub fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("mt_train_test_{}_{}", std::process::id(), name))
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,90 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::features::FeatureMatrix;
+use crate::{LanguageDetectorTrainer, RngStream, TrainingConfig, TrainingExample};
+
+// Unit of the short-text windows cut from training sentences
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowMode {
+    // Train on whole sentences
+    Sentence,
+    // Windows of window_min..=window_max words; sentences without spaces fall back to characters
+    Words,
+    // Windows of window_min..=window_max characters
+    Chars,
+}
+
+// Random window of `length` words or characters; shorter sentences are kept whole
+pub fn sample_window(sentence: &str, mode: WindowMode, length: usize, rng: &mut impl Rng) -> String {
+    let words: Vec<&str> = sentence.split_whitespace().collect();
+    if mode == WindowMode::Words && words.len() > 1 {
+        if words.len() <= length {
+            return sentence.to_string();
+        }
+        let start = rng.random_range(0..=words.len() - length);
+        return words[start..start + length].join(" ");
+    }
+    if mode == WindowMode::Sentence {
+        return sentence.to_string();
+    }
+
+    let chars: Vec<char> = sentence.trim().chars().collect();
+    if chars.len() <= length {
+        return sentence.to_string();
+    }
+    let start = rng.random_range(0..=chars.len() - length);
+    chars[start..start + length].iter().collect()
+}
+
+// One epoch's training examples: each is replaced by a random window with probability
+// window_probability, with a length drawn uniformly from window_min..=window_max
+pub fn windowed_examples(examples: &[TrainingExample], config: &TrainingConfig, rng: &mut impl Rng) -> Vec<TrainingExample> {
+    let min_length = config.window_min.max(1);
+    let max_length = config.window_max.max(min_length);
+    examples.iter()
+        .map(|example| {
+            let mut example = example.clone();
+            if rng.random::<f32>() < config.window_probability {
+                let length = rng.random_range(min_length..=max_length);
+                example.sentence = sample_window(&example.sentence, config.window_mode, length, rng);
+            }
+            example
+        })
+        .collect()
+}
+
+impl LanguageDetectorTrainer {
+    // Accuracy on windows of every length in window_min..=window_max cut from each example
+    // (then on the full sentences), using a fixed random stream so reports are comparable
+    pub fn accuracy_by_length(&self, examples: &[TrainingExample], mode: WindowMode) -> Vec<(Option<usize>, f32)> {
+        let mut rng = self.config.rng(RngStream::EvalWindow);
+        let min_length = self.config.window_min.max(1);
+        let max_length = self.config.window_max.max(min_length);
+        let mut report = Vec::new();
+        for length in min_length..=max_length {
+            let windows: Vec<TrainingExample> = examples.iter()
+                .map(|example| TrainingExample {
+                    sentence: sample_window(&example.sentence, mode, length, &mut rng),
+                    ..example.clone()
+                })
+                .collect();
+            let accuracy = self.held_out_metrics(&FeatureMatrix::build(self, &windows)).accuracy;
+            report.push((Some(length), accuracy));
+        }
+        report.push((None, self.held_out_metrics(&FeatureMatrix::build(self, examples)).accuracy));
+        report
+    }
+
+    pub fn print_accuracy_by_length(&self, label: &str, examples: &[TrainingExample], mode: WindowMode) {
+        let unit = if mode == WindowMode::Chars { "characters" } else { "words" };
+        println!("\n{} accuracy by input length ({}):", label, unit);
+        for (length, accuracy) in self.accuracy_by_length(examples, mode) {
+            match length {
+                Some(length) => println!("  {:>4}: {:.2}%", length, accuracy * 100.0),
+                None => println!("  full: {:.2}%", accuracy * 100.0),
+            }
+        }
+    }
+}